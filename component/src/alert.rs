@@ -0,0 +1,130 @@
+//! Structured anomaly alerts published when a message's novelty crosses the
+//! configured threshold.
+
+use crate::anomaly::{AnomalyScore, FieldDeviation};
+use serde::{Deserialize, Serialize};
+
+/// JSON payload published on the alert subject, tagged `"type": "anomaly"`
+/// to tell it from sequence alerts on the same subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "anomaly")]
+pub(crate) struct AnomalyAlert {
+    /// Subject of the message that triggered the alert.
+    pub subject: String,
+    pub score: AnomalyScore,
+    /// Novelty threshold the score crossed.
+    pub threshold: f64,
+    /// Fields carrying the largest share of the deviation, largest first.
+    pub top_fields: Vec<FieldDeviation>,
+    /// Every field whose JSON type differs from the baseline's, whether or
+    /// not it made `top_fields`.
+    #[serde(default)]
+    pub type_changes: Vec<FieldDeviation>,
+    /// Unix epoch milliseconds at which the message was scored.
+    pub timestamp: u64,
+}
+
+impl AnomalyAlert {
+    /// Build an alert if `score` meets or exceeds `threshold`, keeping at
+    /// most `top_n` of the (already ranked) field deviations.
+    pub(crate) fn from_score(
+        subject: &str,
+        score: AnomalyScore,
+        threshold: f64,
+        mut deviations: Vec<FieldDeviation>,
+        top_n: usize,
+        timestamp: u64,
+    ) -> Option<Self> {
+        if score.novelty < threshold {
+            return None;
+        }
+        let type_changes = deviations
+            .iter()
+            .filter(|d| d.type_changed())
+            .cloned()
+            .collect();
+        deviations.truncate(top_n);
+        Some(Self {
+            subject: subject.to_string(),
+            score,
+            threshold,
+            top_fields: deviations,
+            type_changes,
+            timestamp,
+        })
+    }
+
+    /// Serialise the alert as the published message body.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("alert encode error: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::JsonType;
+
+    fn score(novelty: f64) -> AnomalyScore {
+        AnomalyScore {
+            similarity: 1.0 - novelty,
+            novelty,
+        }
+    }
+
+    fn deviations() -> Vec<FieldDeviation> {
+        ["status", "event", "region"]
+            .iter()
+            .enumerate()
+            .map(|(i, f)| FieldDeviation {
+                field: f.to_string(),
+                similarity: i as f64 * 0.3,
+                json_type: None,
+                baseline_type: None,
+                contribution: 1.0 - i as f64 * 0.3,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_no_alert_below_threshold() {
+        let alert = AnomalyAlert::from_score("s", score(0.3), 0.6, deviations(), 3, 0);
+        assert!(alert.is_none());
+    }
+
+    #[test]
+    fn test_alert_keeps_top_fields() {
+        let alert =
+            AnomalyAlert::from_score("s", score(0.8), 0.6, deviations(), 2, 1_700_000_000_000)
+                .expect("score above threshold must alert");
+        assert_eq!(alert.top_fields.len(), 2);
+        assert_eq!(alert.top_fields[0].field, "status");
+        assert_eq!(alert.timestamp, 1_700_000_000_000);
+        assert!(alert.type_changes.is_empty());
+    }
+
+    #[test]
+    fn test_alert_lists_every_type_change() {
+        let mut deviations = deviations();
+        deviations[2].json_type = Some(JsonType::String);
+        deviations[2].baseline_type = Some(JsonType::Number);
+        let alert = AnomalyAlert::from_score("s", score(0.8), 0.6, deviations, 1, 0).unwrap();
+        assert_eq!(alert.top_fields.len(), 1);
+        assert_eq!(alert.type_changes.len(), 1);
+        assert_eq!(alert.type_changes[0].field, "region");
+    }
+
+    #[test]
+    fn test_alert_json_shape() {
+        let alert =
+            AnomalyAlert::from_score("pattern.monitor.auth", score(0.9), 0.6, deviations(), 1, 5)
+                .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&alert.to_json().unwrap()).unwrap();
+        assert_eq!(json["type"], "anomaly");
+        assert_eq!(json["subject"], "pattern.monitor.auth");
+        assert_eq!(json["threshold"], 0.6);
+        assert_eq!(json["score"]["novelty"], 0.9);
+        assert_eq!(json["top_fields"][0]["field"], "status");
+        assert_eq!(json["timestamp"], 5);
+    }
+}
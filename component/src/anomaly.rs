@@ -0,0 +1,268 @@
+//! Novelty scoring of a message bundle against a subject's stored baseline.
+
+use crate::types::JsonType;
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Result of comparing one message bundle with a baseline bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AnomalyScore {
+    /// Cosine similarity between message and baseline, in `[-1, 1]`.
+    pub similarity: f64,
+    /// `1 - similarity` clamped to `[0, 1]`; higher means more novel.
+    pub novelty: f64,
+}
+
+/// Cosine similarity that treats an empty operand as orthogonal.
+pub(crate) fn similarity(a: &SparseVec, b: &SparseVec) -> f64 {
+    if a.pos.is_empty() && a.neg.is_empty() || b.pos.is_empty() && b.neg.is_empty() {
+        return 0.0;
+    }
+    a.cosine(b)
+}
+
+/// Score `message` against `baseline`.
+pub(crate) fn score_against_baseline(message: &SparseVec, baseline: &SparseVec) -> AnomalyScore {
+    let similarity = similarity(message, baseline);
+    AnomalyScore {
+        similarity,
+        novelty: (1.0 - similarity).clamp(0.0, 1.0),
+    }
+}
+
+/// How closely one bound field vector matches a baseline bundle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FieldDeviation {
+    pub field: String,
+    /// Cosine similarity between the field vector and the baseline.
+    pub similarity: f64,
+    /// JSON type of the field in the scored message.
+    pub json_type: Option<JsonType>,
+    /// Type the field had when last folded into the baseline, if ever.
+    pub baseline_type: Option<JsonType>,
+    /// Share of the message's weighted deviation carried by this field, in
+    /// `[0, 1]`. The contributions of one message sum to 1 unless no field
+    /// deviates at all.
+    #[serde(default)]
+    pub contribution: f64,
+}
+
+impl FieldDeviation {
+    /// Whether the field's type differs from the type learned by the baseline.
+    pub(crate) fn type_changed(&self) -> bool {
+        matches!((self.json_type, self.baseline_type), (Some(a), Some(b)) if a != b)
+    }
+}
+
+/// Compare each field vector of a message with `baseline`, the field
+/// contributing most to the deviation first. A field contributes its
+/// dissimilarity `1 - similarity` times its weight in `weights` (1 if
+/// missing), so fields left out of the message bundle never lead. Ties are
+/// broken by similarity, then field name. `baseline_types` are the field
+/// types recorded with the baseline.
+pub(crate) fn field_deviations(
+    id_to_vec: &HashMap<usize, SparseVec>,
+    id_to_field: &HashMap<usize, String>,
+    id_to_type: &HashMap<usize, JsonType>,
+    weights: &HashMap<usize, f64>,
+    baseline: &SparseVec,
+    baseline_types: &BTreeMap<String, JsonType>,
+) -> Vec<FieldDeviation> {
+    let mut deviations: Vec<(FieldDeviation, f64)> = id_to_vec
+        .iter()
+        .map(|(id, vec)| {
+            let field = id_to_field
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| format!("field_{id}"));
+            let similarity = similarity(vec, baseline);
+            let weight = weights.get(id).copied().unwrap_or(1.0).max(0.0);
+            let deviation = FieldDeviation {
+                similarity,
+                json_type: id_to_type.get(id).copied(),
+                baseline_type: baseline_types.get(&field).copied(),
+                contribution: 0.0,
+                field,
+            };
+            (deviation, weight * (1.0 - similarity).max(0.0))
+        })
+        .collect();
+    let total: f64 = deviations.iter().map(|(_, share)| share).sum();
+    for (deviation, share) in &mut deviations {
+        if total > 0.0 {
+            deviation.contribution = *share / total;
+        }
+    }
+    let mut deviations: Vec<FieldDeviation> = deviations.into_iter().map(|(d, _)| d).collect();
+    deviations.sort_by(|a, b| {
+        b.contribution
+            .total_cmp(&a.contribution)
+            .then_with(|| a.similarity.total_cmp(&b.similarity))
+            .then_with(|| a.field.cmp(&b.field))
+    });
+    deviations
+}
+
+/// One-line summary of the first `top_n` (already ranked) deviations for
+/// logs, e.g. `status 61% (similarity 0.0213), region 20% (similarity 0.6800)`.
+pub(crate) fn describe_deviations(deviations: &[FieldDeviation], top_n: usize) -> String {
+    deviations
+        .iter()
+        .take(top_n)
+        .map(|d| {
+            format!(
+                "{} {:.0}% (similarity {:.4})",
+                d.field,
+                d.contribution * 100.0,
+                d.similarity
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field_ids::FieldIds;
+    use crate::{build_master_bundle, encode_json_fields, EncoderConfig};
+
+    fn bundle_of(body: &[u8]) -> SparseVec {
+        build_master_bundle(
+            &encode_json_fields(body, &EncoderConfig::default(), &mut FieldIds::default())
+                .unwrap()
+                .id_to_vec,
+            &HashMap::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_identical_message_has_no_novelty() {
+        let message = bundle_of(br#"{"event":"quake","magnitude":"6.2"}"#);
+        let score = score_against_baseline(&message, &message);
+        assert!(score.novelty < 1e-9, "got {score:?}");
+    }
+
+    #[test]
+    fn test_changed_field_is_more_novel_than_repeat() {
+        let baseline = bundle_of(br#"{"event":"quake","status":"ok","region":"pacific"}"#);
+        let repeat = bundle_of(br#"{"event":"quake","status":"ok","region":"pacific"}"#);
+        let changed = bundle_of(br#"{"event":"quake","status":"failed","region":"pacific"}"#);
+        let unrelated = bundle_of(br#"{"user":"alice","action":"login"}"#);
+
+        let repeat_score = score_against_baseline(&repeat, &baseline);
+        let changed_score = score_against_baseline(&changed, &baseline);
+        let unrelated_score = score_against_baseline(&unrelated, &baseline);
+        assert!(repeat_score.novelty < changed_score.novelty);
+        assert!(changed_score.novelty < unrelated_score.novelty);
+    }
+
+    #[test]
+    fn test_field_deviations_rank_changed_field_first() {
+        let baseline = bundle_of(br#"{"event":"quake","status":"ok","region":"pacific"}"#);
+        let changed = encode_json_fields(
+            br#"{"event":"quake","status":"failed","region":"pacific"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let deviations = field_deviations(
+            &changed.id_to_vec,
+            &changed.id_to_field,
+            &changed.id_to_type,
+            &HashMap::new(),
+            &baseline,
+            &BTreeMap::new(),
+        );
+        assert_eq!(deviations.len(), 3);
+        assert_eq!(deviations[0].field, "status");
+        assert!(deviations[0].similarity < deviations[1].similarity);
+        assert!(deviations.iter().all(|d| !d.type_changed()));
+    }
+
+    #[test]
+    fn test_field_deviations_flag_type_change() {
+        let baseline = bundle_of(br#"{"event":"quake","magnitude":6.2}"#);
+        let baseline_types = BTreeMap::from([
+            ("event".to_string(), JsonType::String),
+            ("magnitude".to_string(), JsonType::Number),
+        ]);
+        let changed = encode_json_fields(
+            br#"{"event":"quake","magnitude":"6.2"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let deviations = field_deviations(
+            &changed.id_to_vec,
+            &changed.id_to_field,
+            &changed.id_to_type,
+            &HashMap::new(),
+            &baseline,
+            &baseline_types,
+        );
+        let magnitude = deviations.iter().find(|d| d.field == "magnitude").unwrap();
+        assert_eq!(magnitude.json_type, Some(JsonType::String));
+        assert_eq!(magnitude.baseline_type, Some(JsonType::Number));
+        assert!(magnitude.type_changed());
+        assert_eq!(deviations[0].field, "magnitude");
+        assert!(magnitude.similarity < 0.2, "got {}", magnitude.similarity);
+        assert!(!deviations[1].type_changed());
+    }
+
+    #[test]
+    fn test_contributions_share_the_deviation_by_weight() {
+        let baseline = bundle_of(br#"{"event":"quake","status":"ok","request_id":"r1"}"#);
+        let changed = encode_json_fields(
+            br#"{"event":"quake","status":"failed","request_id":"r2"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let field_id = |name: &str| {
+            changed
+                .id_to_field
+                .iter()
+                .find(|(_, f)| f.as_str() == name)
+                .map(|(id, _)| *id)
+                .unwrap()
+        };
+        let deviations = |weights: &HashMap<usize, f64>| {
+            field_deviations(
+                &changed.id_to_vec,
+                &changed.id_to_field,
+                &changed.id_to_type,
+                weights,
+                &baseline,
+                &BTreeMap::new(),
+            )
+        };
+
+        let even = deviations(&HashMap::new());
+        let total: f64 = even.iter().map(|d| d.contribution).sum();
+        assert!((total - 1.0).abs() < 1e-9, "got {total}");
+        assert!(even[2].field == "event" && even[2].contribution < even[1].contribution);
+
+        // An unweighted identifier never leads the breakdown.
+        let weighted = deviations(&HashMap::from([(field_id("request_id"), 0.0)]));
+        assert_eq!(weighted[0].field, "status");
+        assert!(weighted[0].contribution > even[0].contribution.min(even[1].contribution));
+        let request_id = weighted.iter().find(|d| d.field == "request_id").unwrap();
+        assert_eq!(request_id.contribution, 0.0);
+        assert!(request_id.similarity < 0.5);
+
+        let summary = describe_deviations(&weighted, 1);
+        assert!(summary.starts_with("status "), "got {summary}");
+        assert!(!summary.contains(','), "got {summary}");
+    }
+
+    #[test]
+    fn test_empty_vectors_score_as_fully_novel() {
+        let empty = SparseVec::new();
+        let score = score_against_baseline(&empty, &empty);
+        assert_eq!(score.similarity, 0.0);
+        assert_eq!(score.novelty, 1.0);
+    }
+}
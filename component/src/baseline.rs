@@ -0,0 +1,330 @@
+//! Per-subject baseline: the accumulated master bundle and its metadata.
+//!
+//! The master bundle is the running superposition of every message bundle of
+//! a subject. Ternary vectors cannot carry how many messages agreed on a
+//! dimension, so the superposition is kept as [`BundleVotes`] under
+//! `bundle-votes:v1:{subject}` and thresholded into the bundle stored under
+//! `bundle:v1:{subject}`. Every message moves the votes by one, so a shape
+//! that becomes common takes over the bundle however many messages came
+//! before it.
+
+use crate::anomaly::{AnomalyScore, FieldDeviation};
+use crate::decay::SUPPORT_DIVISOR;
+use crate::types::JsonType;
+use crate::weights::FieldVariability;
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Metadata stored next to a subject's master bundle under
+/// `bundle-meta:v1:{subject}` as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BundleMeta {
+    /// Number of messages superposed into the stored bundle.
+    pub message_count: u64,
+    /// Novelty statistics of messages scored against this baseline.
+    #[serde(default)]
+    pub scores: ScoreStats,
+    /// JSON type of each field when last folded into the bundle, so type
+    /// changes can be reported apart from value changes.
+    #[serde(default)]
+    pub field_types: BTreeMap<String, JsonType>,
+    /// How much each field varies against the baseline, for learned weights.
+    #[serde(default)]
+    pub field_variability: BTreeMap<String, FieldVariability>,
+}
+
+impl BundleMeta {
+    /// Parse metadata bytes read from the bucket.
+    pub(crate) fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|e| format!("bundle metadata parse error: {e}"))
+    }
+
+    /// Serialise metadata for storage in the bucket.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("bundle metadata encode error: {e}"))
+    }
+
+    /// Record each field's similarity to the baseline.
+    pub(crate) fn record_field_deviations(&mut self, deviations: &[FieldDeviation]) {
+        for deviation in deviations {
+            self.field_variability
+                .entry(deviation.field.clone())
+                .or_default()
+                .record(deviation.similarity);
+        }
+    }
+
+    /// Remember the type each field had in the latest message.
+    pub(crate) fn record_field_types<'a>(
+        &mut self,
+        fields: impl IntoIterator<Item = (&'a String, JsonType)>,
+    ) {
+        for (field, json_type) in fields {
+            self.field_types.insert(field.clone(), json_type);
+        }
+    }
+}
+
+/// Running statistics of novelty scores for one subject.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ScoreStats {
+    /// Number of messages scored (the first message of a subject is not).
+    pub count: u64,
+    /// Most recent score.
+    pub last: Option<AnomalyScore>,
+    /// Mean novelty over all scored messages.
+    pub mean_novelty: f64,
+    /// Highest novelty seen.
+    pub max_novelty: f64,
+}
+
+impl ScoreStats {
+    /// Record one more score.
+    pub(crate) fn record(&mut self, score: AnomalyScore) {
+        self.count += 1;
+        self.mean_novelty += (score.novelty - self.mean_novelty) / self.count as f64;
+        self.max_novelty = self.max_novelty.max(score.novelty);
+        self.last = Some(score);
+    }
+}
+
+/// Per-dimension votes of superposed message bundles: the number of
+/// messages holding `+1` at a dimension minus those holding `-1`.
+/// Dimensions whose votes cancel out are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BundleVotes(pub BTreeMap<usize, i64>);
+
+impl BundleVotes {
+    /// Parse votes read from the bucket.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes).map_err(|e| format!("bundle votes decode error: {e}"))
+    }
+
+    /// Serialise the votes for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("bundle votes encode error: {e}"))
+    }
+
+    /// Votes of `count` messages equal to `bundle`, to carry on from a
+    /// bundle stored before its votes were kept.
+    pub(crate) fn seeded(bundle: &SparseVec, count: u64) -> Self {
+        let mut votes = Self::default();
+        let count = i64::try_from(count.max(1)).unwrap_or(i64::MAX);
+        for (dims, vote) in [(&bundle.pos, count), (&bundle.neg, -count)] {
+            votes.0.extend(dims.iter().map(|dim| (*dim, vote)));
+        }
+        votes
+    }
+
+    /// Count one more message.
+    pub(crate) fn add(&mut self, message: &SparseVec) {
+        for (dims, vote) in [(&message.pos, 1), (&message.neg, -1)] {
+            for dim in dims {
+                *self.0.entry(*dim).or_default() += vote;
+            }
+        }
+        self.0.retain(|_, vote| *vote != 0);
+    }
+
+    /// Count the messages behind `other` as well.
+    pub(crate) fn absorb(&mut self, other: &Self) {
+        for (dim, vote) in &other.0 {
+            *self.0.entry(*dim).or_default() += vote;
+        }
+        self.0.retain(|_, vote| *vote != 0);
+    }
+
+    /// The superposition as a ternary vector: the sign of every vote at
+    /// least a quarter as strong as the strongest one.
+    pub(crate) fn bundle(&self) -> SparseVec {
+        let strongest = self.0.values().map(|v| v.unsigned_abs()).max().unwrap_or(0);
+        let threshold = strongest.div_ceil(u64::from(SUPPORT_DIVISOR));
+        let mut bundle = SparseVec::new();
+        for (dim, vote) in &self.0 {
+            if vote.unsigned_abs() >= threshold {
+                if *vote > 0 {
+                    bundle.pos.push(*dim);
+                } else {
+                    bundle.neg.push(*dim);
+                }
+            }
+        }
+        bundle
+    }
+}
+
+/// Fold one more message into the stored votes and metadata, returning the
+/// values to persist; the master bundle is [`BundleVotes::bundle`] of the
+/// votes. `score` is the message's score against the stored bundle, if there
+/// was one.
+pub(crate) fn update_baseline(
+    stored: Option<(&BundleVotes, &BundleMeta)>,
+    message: &SparseVec,
+    score: Option<AnomalyScore>,
+) -> (BundleVotes, BundleMeta) {
+    let mut votes = stored.map(|(v, _)| v.clone()).unwrap_or_default();
+    votes.add(message);
+    let mut meta = stored.map(|(_, m)| m.clone()).unwrap_or_default();
+    meta.message_count += 1;
+    if let Some(score) = score {
+        meta.scores.record(score);
+    }
+    (votes, meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::sparse_code;
+    use embeddenator_vsa::ReversibleVSAConfig;
+
+    fn vec_of(data: &[u8]) -> SparseVec {
+        SparseVec::encode_data(data, &ReversibleVSAConfig::default(), None)
+    }
+
+    fn accumulate(messages: &[&SparseVec]) -> BundleVotes {
+        let mut votes = BundleVotes::default();
+        for message in messages {
+            votes.add(message);
+        }
+        votes
+    }
+
+    #[test]
+    fn test_first_message_is_its_own_bundle() {
+        let message = vec_of(b"first message");
+        let bundle = accumulate(&[&message]).bundle();
+        assert_eq!(bundle.pos, message.pos);
+        assert_eq!(bundle.neg, message.neg);
+        assert!(BundleVotes::default().bundle().pos.is_empty());
+    }
+
+    #[test]
+    fn test_bundle_retains_earlier_messages() {
+        let first = vec_of(b"first message");
+        let second = vec_of(b"another payload entirely");
+        let bundle = accumulate(&[&first, &second]).bundle();
+        assert!(
+            bundle.cosine(&first) > 0.3,
+            "accumulated bundle must stay similar to the earlier message"
+        );
+        assert!(bundle.cosine(&second) > 0.3);
+    }
+
+    #[test]
+    fn test_one_message_weighs_as_one_vote() {
+        let usual = vec_of(b"the usual payload");
+        let odd = vec_of(b"one odd message");
+        let mut votes = BundleVotes::seeded(&usual, 50);
+        votes.add(&odd);
+        let bundle = votes.bundle();
+        assert!(bundle.cosine(&usual) > 0.9, "got {}", bundle.cosine(&usual));
+        assert!(bundle.cosine(&odd) < accumulate(&[&usual, &odd]).bundle().cosine(&odd));
+    }
+
+    #[test]
+    fn test_bundle_moves_to_a_new_shape() {
+        let (a, b) = (sparse_code("shape a", 400), sparse_code("shape b", 400));
+        let mut votes = BundleVotes::default();
+        for _ in 0..100 {
+            votes.add(&a);
+        }
+        let before = votes.bundle().cosine(&b);
+        for _ in 0..500 {
+            votes.add(&b);
+        }
+        let bundle = votes.bundle();
+        assert!(bundle.cosine(&b) > 0.99, "got {}", bundle.cosine(&b));
+        assert!(bundle.cosine(&b) > before);
+        assert!(bundle.cosine(&a) < 0.1, "got {}", bundle.cosine(&a));
+    }
+
+    #[test]
+    fn test_votes_absorb_and_round_trip() {
+        let (a, b) = (vec_of(b"left"), vec_of(b"right"));
+        let mut votes = accumulate(&[&a, &a]);
+        votes.absorb(&accumulate(&[&b]));
+        assert_eq!(votes, accumulate(&[&a, &a, &b]));
+        assert_eq!(
+            BundleVotes::from_bytes(&votes.to_bytes().unwrap()).unwrap(),
+            votes
+        );
+    }
+
+    #[test]
+    fn test_update_baseline_counts_messages() {
+        let message = vec_of(b"payload");
+        let (votes, meta) = update_baseline(None, &message, None);
+        assert_eq!(meta.message_count, 1);
+        assert_eq!(meta.scores.count, 0);
+        let (votes, meta) = update_baseline(Some((&votes, &meta)), &message, None);
+        assert_eq!(meta.message_count, 2);
+        assert_eq!(votes, BundleVotes::seeded(&message, 2));
+    }
+
+    #[test]
+    fn test_score_stats_track_mean_and_max() {
+        let mut stats = ScoreStats::default();
+        for novelty in [0.2, 0.6, 0.4] {
+            stats.record(AnomalyScore {
+                similarity: 1.0 - novelty,
+                novelty,
+            });
+        }
+        assert_eq!(stats.count, 3);
+        assert!((stats.mean_novelty - 0.4).abs() < 1e-9);
+        assert!((stats.max_novelty - 0.6).abs() < 1e-9);
+        assert_eq!(stats.last.map(|s| s.novelty), Some(0.4));
+    }
+
+    #[test]
+    fn test_bundle_meta_json_roundtrip() {
+        let meta = BundleMeta {
+            message_count: 42,
+            ..Default::default()
+        };
+        let bytes = meta.to_json().unwrap();
+        assert_eq!(BundleMeta::from_json(&bytes).unwrap(), meta);
+        assert!(BundleMeta::from_json(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_bundle_meta_without_scores_still_parses() {
+        let meta = BundleMeta::from_json(br#"{"message_count":3}"#).unwrap();
+        assert_eq!(meta.message_count, 3);
+        assert_eq!(meta.scores, ScoreStats::default());
+        assert!(meta.field_types.is_empty());
+    }
+
+    #[test]
+    fn test_record_field_deviations_tracks_variability() {
+        let mut meta = BundleMeta::default();
+        let deviation = |field: &str, similarity| FieldDeviation {
+            field: field.to_string(),
+            similarity,
+            json_type: None,
+            baseline_type: None,
+            contribution: 0.0,
+        };
+        meta.record_field_deviations(&[deviation("status", 1.0), deviation("id", 0.0)]);
+        meta.record_field_deviations(&[deviation("status", 0.8), deviation("id", 0.0)]);
+        assert_eq!(meta.field_variability["status"].count, 2);
+        assert!((meta.field_variability["status"].mean_novelty - 0.1).abs() < 1e-9);
+        assert_eq!(meta.field_variability["id"].mean_novelty, 1.0);
+    }
+
+    #[test]
+    fn test_record_field_types_keeps_latest_type() {
+        let mut meta = BundleMeta::default();
+        let magnitude = "magnitude".to_string();
+        meta.record_field_types([(&magnitude, JsonType::Number)]);
+        meta.record_field_types([(&magnitude, JsonType::String)]);
+        assert_eq!(meta.field_types.get("magnitude"), Some(&JsonType::String));
+        let bytes = meta.to_json().unwrap();
+        assert!(String::from_utf8(bytes)
+            .unwrap()
+            .contains(r#""magnitude":"string""#));
+    }
+}
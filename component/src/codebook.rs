@@ -0,0 +1,180 @@
+//! Cleanup memory of observed field values.
+//!
+//! Binding is reversible: unbinding a subject's master bundle with the role
+//! vector of a field yields a noisy superposition of the values that field
+//! has carried. On its own that vector is unreadable, so each subject keeps a
+//! codebook of the value vectors it has seen per field, stored under
+//! `codebook:v1:{subject}` as bincode. Comparing the unbound vector with the
+//! codebook ranks the known values by how strongly the bundle holds them,
+//! answering "what value does `event` typically have on this subject?".
+
+use crate::hierarchy::unbind_path;
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Default number of distinct values remembered per field.
+pub(crate) const DEFAULT_CODEBOOK_LIMIT: usize = 64;
+
+/// One remembered value of a field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CodebookEntry {
+    /// The value as JSON text.
+    pub value: String,
+    /// Messages the value was seen in.
+    pub count: u64,
+    /// The value vector, before binding to the field role.
+    pub vector: SparseVec,
+}
+
+/// A ranked candidate value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ValueCandidate {
+    pub value: String,
+    pub count: u64,
+    /// Cosine similarity of the value vector to the unbound bundle.
+    pub similarity: f64,
+}
+
+/// Remembered values of each field of one subject.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Codebook {
+    pub fields: BTreeMap<String, Vec<CodebookEntry>>,
+}
+
+impl Codebook {
+    /// Parse a codebook read from the bucket.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes).map_err(|e| format!("codebook decode error: {e}"))
+    }
+
+    /// Serialise the codebook for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("codebook encode error: {e}"))
+    }
+
+    /// Record that `field` carried `value`, given its bound field vector.
+    /// When the field already holds `limit` values, the least seen one
+    /// (the oldest among equals) makes room for a new value.
+    pub(crate) fn observe(&mut self, field: &str, value: &str, bound: &SparseVec, limit: usize) {
+        if limit == 0 {
+            return;
+        }
+        let entries = self.fields.entry(field.to_string()).or_default();
+        if let Some(entry) = entries.iter_mut().find(|e| e.value == value) {
+            entry.count += 1;
+            return;
+        }
+        if entries.len() >= limit {
+            if let Some(rarest) = entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, e)| e.count)
+                .map(|(i, _)| i)
+            {
+                entries.remove(rarest);
+            }
+        }
+        entries.push(CodebookEntry {
+            value: value.to_string(),
+            count: 1,
+            vector: unbind_path(bound, &[field]),
+        });
+    }
+
+    /// Known values of `field` ranked by how strongly `bundle` holds them,
+    /// strongest first.
+    pub(crate) fn recall(&self, bundle: &SparseVec, field: &str) -> Vec<ValueCandidate> {
+        let unbound = unbind_path(bundle, &[field]);
+        let mut candidates: Vec<ValueCandidate> = self
+            .fields
+            .get(field)
+            .into_iter()
+            .flatten()
+            .map(|entry| ValueCandidate {
+                value: entry.value.clone(),
+                count: entry.count,
+                similarity: unbound.cosine(&entry.vector),
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.similarity
+                .total_cmp(&a.similarity)
+                .then_with(|| b.count.cmp(&a.count))
+        });
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field_ids::FieldIds;
+    use crate::{build_master_bundle, encode_json_fields, EncoderConfig};
+    use std::collections::HashMap;
+
+    fn observe_messages(bodies: &[&[u8]]) -> (Codebook, SparseVec) {
+        let mut codebook = Codebook::default();
+        let mut field_ids = FieldIds::default();
+        let mut master: Option<SparseVec> = None;
+        for body in bodies {
+            let encoded =
+                encode_json_fields(body, &EncoderConfig::default(), &mut field_ids).unwrap();
+            for (id, bound) in &encoded.id_to_vec {
+                let field = &encoded.id_to_field[id];
+                codebook.observe(field, &encoded.id_to_value[id], bound, 8);
+            }
+            let bundle = build_master_bundle(&encoded.id_to_vec, &HashMap::new()).unwrap();
+            master = Some(match master {
+                Some(master) => master.bundle(&bundle),
+                None => bundle,
+            });
+        }
+        (codebook, master.unwrap())
+    }
+
+    #[test]
+    fn test_recall_ranks_the_usual_value_first() {
+        let (codebook, master) = observe_messages(&[
+            br#"{"event":"quake","region":"pacific"}"#,
+            br#"{"event":"quake","region":"atlantic"}"#,
+            br#"{"event":"quake","region":"pacific"}"#,
+            br#"{"event":"flood","region":"pacific"}"#,
+        ]);
+        let events = codebook.recall(&master, "event");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].value, r#""quake""#);
+        assert_eq!(events[0].count, 3);
+        assert!(events[0].similarity > events[1].similarity);
+        assert!(codebook.recall(&master, "missing").is_empty());
+    }
+
+    #[test]
+    fn test_rarest_value_makes_room() {
+        let mut codebook = Codebook::default();
+        let vector = crate::symbols::sparse_code("v", 8);
+        codebook.observe("f", "a", &vector, 2);
+        codebook.observe("f", "a", &vector, 2);
+        codebook.observe("f", "b", &vector, 2);
+        codebook.observe("f", "c", &vector, 2);
+        let values: Vec<&str> = codebook.fields["f"]
+            .iter()
+            .map(|e| e.value.as_str())
+            .collect();
+        assert_eq!(values, ["a", "c"]);
+        codebook.observe("g", "x", &vector, 0);
+        assert!(!codebook.fields.contains_key("g"));
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        let (codebook, _) = observe_messages(&[br#"{"event":"quake"}"#]);
+        let restored = Codebook::from_bytes(&codebook.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.fields["event"][0].value, r#""quake""#);
+        assert_eq!(
+            restored.fields["event"][0].vector.pos,
+            codebook.fields["event"][0].vector.pos
+        );
+    }
+}
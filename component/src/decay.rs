@@ -0,0 +1,149 @@
+//! Exponentially decaying baseline.
+//!
+//! The master bundle superposes every message a subject has seen, so after
+//! enough messages it saturates and stops reacting to slow drift. The
+//! decaying baseline instead keeps one signed integer accumulator per
+//! dimension, stored under `bundle-decay:v1:{subject}` as bincode. Each
+//! message first scales the accumulator by `0.5^(1 / half_life)` and then
+//! adds [`DECAY_SCALE`] for every `+1` of the message bundle and subtracts it
+//! for every `-1`, so a message's contribution halves every `half_life`
+//! messages. Thresholding the accumulator gives back a [`SparseVec`] that
+//! follows gradual change but is still far from an abrupt one.
+
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::{SparseVec, DIM};
+use serde::{Deserialize, Serialize};
+
+/// Weight of one message in the accumulator.
+pub(crate) const DECAY_SCALE: i32 = 1 << 16;
+
+/// Largest accepted half-life, in messages. The accumulator of a component
+/// carried by every message settles near `DECAY_SCALE * 1.44 * half_life`,
+/// which must fit an `i32`.
+pub(crate) const MAX_HALF_LIFE: u32 = 10_000;
+
+/// Components whose magnitude is below this fraction of the largest one are
+/// left out of the thresholded bundle.
+pub(crate) const SUPPORT_DIVISOR: u32 = 4;
+
+/// Decaying accumulator of one subject's message bundles.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct DecayingBundle {
+    /// Messages folded in, however faded.
+    pub message_count: u64,
+    /// One accumulator per dimension; empty until the first message.
+    pub acc: Vec<i32>,
+}
+
+impl DecayingBundle {
+    /// Parse a decaying bundle read from the bucket.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes).map_err(|e| format!("decaying bundle decode error: {e}"))
+    }
+
+    /// Serialise the decaying bundle for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("decaying bundle encode error: {e}"))
+    }
+
+    /// Fade earlier messages by one step of `half_life` (in messages), then
+    /// add `message`.
+    pub(crate) fn fold(&mut self, message: &SparseVec, half_life: u32) {
+        if self.acc.is_empty() {
+            self.acc = vec![0; DIM];
+        }
+        let factor = 0.5f64.powf(1.0 / f64::from(half_life.max(1)));
+        for value in &mut self.acc {
+            // Truncation rounds toward zero, so faded components reach 0.
+            *value = (f64::from(*value) * factor) as i32;
+        }
+        for idx in &message.pos {
+            if let Some(value) = self.acc.get_mut(*idx) {
+                *value = value.saturating_add(DECAY_SCALE);
+            }
+        }
+        for idx in &message.neg {
+            if let Some(value) = self.acc.get_mut(*idx) {
+                *value = value.saturating_sub(DECAY_SCALE);
+            }
+        }
+        self.message_count += 1;
+    }
+
+    /// The baseline as a ternary vector: the sign of every component at
+    /// least a quarter as strong as the strongest one.
+    pub(crate) fn bundle(&self) -> SparseVec {
+        let strongest = self.acc.iter().map(|v| v.unsigned_abs()).max().unwrap_or(0);
+        let mut bundle = SparseVec::new();
+        if strongest == 0 {
+            return bundle;
+        }
+        let threshold = strongest.div_ceil(SUPPORT_DIVISOR);
+        for (idx, value) in self.acc.iter().enumerate() {
+            if value.unsigned_abs() >= threshold {
+                if *value > 0 {
+                    bundle.pos.push(idx);
+                } else {
+                    bundle.neg.push(idx);
+                }
+            }
+        }
+        bundle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::sparse_code;
+
+    #[test]
+    fn test_single_message_is_its_own_baseline() {
+        let message = sparse_code("a", 200);
+        let mut decaying = DecayingBundle::default();
+        assert!(decaying.bundle().pos.is_empty());
+        decaying.fold(&message, 10);
+        let bundle = decaying.bundle();
+        assert_eq!((bundle.pos, bundle.neg), (message.pos, message.neg));
+    }
+
+    #[test]
+    fn test_baseline_follows_slow_drift() {
+        let (old, new) = (sparse_code("old", 200), sparse_code("new", 200));
+        let mut decaying = DecayingBundle::default();
+        for _ in 0..50 {
+            decaying.fold(&old, 10);
+        }
+        assert!(decaying.bundle().cosine(&old) > 0.99);
+        for _ in 0..50 {
+            decaying.fold(&new, 10);
+        }
+        // Fifty messages are five half-lives: the old pattern has faded out.
+        let bundle = decaying.bundle();
+        assert!(bundle.cosine(&new) > 0.99, "got {}", bundle.cosine(&new));
+        assert!(bundle.cosine(&old) < 0.1);
+        assert_eq!(decaying.message_count, 100);
+    }
+
+    #[test]
+    fn test_abrupt_change_stays_novel() {
+        let usual = sparse_code("usual", 200);
+        let mut decaying = DecayingBundle::default();
+        for _ in 0..100 {
+            decaying.fold(&usual, 50);
+        }
+        decaying.fold(&sparse_code("burst", 200), 50);
+        let bundle = decaying.bundle();
+        assert!(bundle.cosine(&usual) > 0.99);
+        assert!(bundle.cosine(&sparse_code("burst", 200)) < 0.1);
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        let mut decaying = DecayingBundle::default();
+        decaying.fold(&sparse_code("a", 50), 10);
+        let restored = DecayingBundle::from_bytes(&decaying.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.message_count, 1);
+        assert_eq!(restored.acc, decaying.acc);
+    }
+}
@@ -0,0 +1,208 @@
+//! Concept drift between successive time windows.
+//!
+//! Anomaly scores judge single messages; drift judges a subject's pattern as
+//! a whole. When a new time window opens (see [`crate::window`]), the two
+//! latest complete windows are compared by cosine similarity. If the
+//! magnitude `1 - similarity` reaches `drift_threshold`, a drift event is
+//! published on `drift_subject` and stored under
+//! `drift:v1:{subject}:{start}` for audit.
+//!
+//! The fields most responsible are found with the subject's codebook (see
+//! [`crate::codebook`]): each field's known values are recalled from both
+//! window bundles, and a field whose values shifted shows two dissimilar
+//! recall profiles. Fields missing from the codebook are not ranked.
+
+use crate::anomaly::similarity;
+use crate::codebook::Codebook;
+use crate::hierarchy::unbind_path;
+use crate::window::{WindowBundle, WindowSize};
+use serde::{Deserialize, Serialize};
+
+/// Default subject drift events are published to.
+pub(crate) const DEFAULT_DRIFT_SUBJECT: &str = "pattern.drift";
+
+/// Default drift magnitude at or above which an event is published.
+pub(crate) const DEFAULT_DRIFT_THRESHOLD: f64 = 0.4;
+
+/// How much one field's values moved between two windows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FieldDrift {
+    pub field: String,
+    /// Correlation between the field's recall profiles in both windows (how
+    /// strongly each known value is held), in `[-1, 1]`.
+    pub similarity: f64,
+}
+
+/// JSON payload published on the drift subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "drift")]
+pub(crate) struct DriftEvent {
+    pub subject: String,
+    pub window_size: WindowSize,
+    /// Start of the earlier window, Unix epoch milliseconds.
+    pub previous_start_ms: u64,
+    /// Start of the later window, Unix epoch milliseconds.
+    pub current_start_ms: u64,
+    /// Cosine similarity between the two window bundles.
+    pub similarity: f64,
+    /// `1 - similarity` clamped to `[0, 1]`.
+    pub magnitude: f64,
+    /// Magnitude threshold the drift crossed.
+    pub threshold: f64,
+    /// Fields whose values moved most, least similar first.
+    pub top_fields: Vec<FieldDrift>,
+    /// Unix epoch milliseconds at which the drift was detected.
+    pub timestamp: u64,
+}
+
+impl DriftEvent {
+    /// Compare `previous` with `current` and build an event if the drift
+    /// magnitude meets or exceeds `threshold`, naming at most `top_n` of
+    /// the fields in `codebook`.
+    pub(crate) fn detect(
+        subject: &str,
+        previous: &WindowBundle,
+        current: &WindowBundle,
+        codebook: &Codebook,
+        threshold: f64,
+        top_n: usize,
+        timestamp: u64,
+    ) -> Option<Self> {
+        let similarity = similarity(&previous.bundle, &current.bundle);
+        let magnitude = (1.0 - similarity).clamp(0.0, 1.0);
+        if magnitude < threshold {
+            return None;
+        }
+        let mut top_fields: Vec<FieldDrift> = codebook
+            .fields
+            .iter()
+            .map(|(field, entries)| {
+                let profile = |window: &WindowBundle| -> Vec<f64> {
+                    let unbound = unbind_path(&window.bundle, &[field]);
+                    entries.iter().map(|e| unbound.cosine(&e.vector)).collect()
+                };
+                FieldDrift {
+                    field: field.clone(),
+                    similarity: profile_similarity(&profile(previous), &profile(current)),
+                }
+            })
+            .collect();
+        top_fields.sort_by(|a, b| {
+            a.similarity
+                .total_cmp(&b.similarity)
+                .then_with(|| a.field.cmp(&b.field))
+        });
+        top_fields.truncate(top_n);
+        Some(Self {
+            subject: subject.to_string(),
+            window_size: current.size,
+            previous_start_ms: previous.start_ms,
+            current_start_ms: current.start_ms,
+            similarity,
+            magnitude,
+            threshold,
+            top_fields,
+            timestamp,
+        })
+    }
+
+    /// Serialise the event as the published message body.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("drift event encode error: {e}"))
+    }
+}
+
+/// Pearson correlation of two recall profiles. Similar values (short
+/// strings, nearby numbers) are recalled together; centring each profile
+/// removes that shared part so a shift between them still shows. Profiles
+/// without spread (a field with one known value) count as unchanged if both
+/// are flat.
+fn profile_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let centred = |v: &[f64]| -> Vec<f64> {
+        let mean = v.iter().sum::<f64>() / v.len().max(1) as f64;
+        v.iter().map(|x| x - mean).collect()
+    };
+    let (a, b) = (centred(a), centred(b));
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let (norm_a, norm_b) = (norm(&a), norm(&b));
+    if norm_a < 1e-12 || norm_b < 1e-12 {
+        return if norm_a < 1e-12 && norm_b < 1e-12 {
+            1.0
+        } else {
+            0.0
+        };
+    }
+    a.iter().zip(&b).map(|(x, y)| x * y).sum::<f64>() / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field_ids::FieldIds;
+    use crate::{build_master_bundle, encode_json_fields, EncoderConfig};
+    use std::collections::HashMap;
+
+    /// One window of `bodies`, recording their values in `codebook`.
+    fn window(start_ms: u64, bodies: &[&[u8]], codebook: &mut Codebook) -> WindowBundle {
+        let mut field_ids = FieldIds::default();
+        let mut window: Option<WindowBundle> = None;
+        for body in bodies {
+            let encoded =
+                encode_json_fields(body, &EncoderConfig::default(), &mut field_ids).unwrap();
+            for (id, bound) in &encoded.id_to_vec {
+                codebook.observe(&encoded.id_to_field[id], &encoded.id_to_value[id], bound, 8);
+            }
+            let bundle = build_master_bundle(&encoded.id_to_vec, &HashMap::new()).unwrap();
+            match &mut window {
+                Some(window) => window.fold(&bundle),
+                None => window = Some(WindowBundle::new(WindowSize::Hour, start_ms, &bundle)),
+            }
+        }
+        window.unwrap()
+    }
+
+    #[test]
+    fn test_stable_windows_do_not_drift() {
+        let mut codebook = Codebook::default();
+        let body: &[u8] = br#"{"event":"order","region":"eu","status":"ok"}"#;
+        let previous = window(0, &[body, body], &mut codebook);
+        let current = window(3_600_000, &[body], &mut codebook);
+        assert!(DriftEvent::detect("s", &previous, &current, &codebook, 0.4, 3, 0).is_none());
+    }
+
+    #[test]
+    fn test_shifted_fields_are_named() {
+        let mut codebook = Codebook::default();
+        let previous = window(
+            0,
+            &[br#"{"event":"order","region":"eu","status":"ok"}"#],
+            &mut codebook,
+        );
+        let current = window(
+            3_600_000,
+            &[br#"{"event":"order","region":"us","status":"failed"}"#],
+            &mut codebook,
+        );
+        let event = DriftEvent::detect("orders", &previous, &current, &codebook, 0.4, 2, 9)
+            .expect("two of three fields changed");
+        assert!(event.magnitude >= 0.4, "got {}", event.magnitude);
+        assert_eq!(event.top_fields.len(), 2);
+        assert!(
+            event.top_fields[1].similarity < 0.0,
+            "got {:?}",
+            event.top_fields
+        );
+        let named: Vec<&str> = event.top_fields.iter().map(|f| f.field.as_str()).collect();
+        assert!(
+            named.contains(&"region") && named.contains(&"status"),
+            "got {named:?}"
+        );
+        assert_eq!(event.previous_start_ms, 0);
+        assert_eq!(event.current_start_ms, 3_600_000);
+
+        let json: serde_json::Value = serde_json::from_slice(&event.to_json().unwrap()).unwrap();
+        assert_eq!(json["type"], "drift");
+        assert_eq!(json["window_size"], "hour");
+        assert_eq!(json["subject"], "orders");
+    }
+}
@@ -0,0 +1,67 @@
+//! Stable field ids.
+//!
+//! Field vectors are addressed by a numeric id in the bundle, the index and
+//! the alert pipeline. Ids are handed out once per field path and remembered
+//! in a per-subject dictionary stored under `field-ids:v1:{subject}`, so a
+//! field keeps its id across messages whatever the key order of the JSON.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Field path → id dictionary of one subject.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FieldIds {
+    /// Id of every field path seen so far.
+    pub ids: BTreeMap<String, usize>,
+    /// Id given to the next new path. Ids are never reused, so a removed
+    /// field cannot hand its id to another one.
+    pub next_id: usize,
+}
+
+impl FieldIds {
+    /// Parse a dictionary read from the bucket.
+    pub(crate) fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|e| format!("field id dictionary parse error: {e}"))
+    }
+
+    /// Serialise the dictionary for storage in the bucket.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("field id dictionary encode error: {e}"))
+    }
+
+    /// Id of `path`, assigning the next free id if the path is new.
+    pub(crate) fn id_for(&mut self, path: &str) -> usize {
+        if let Some(&id) = self.ids.get(path) {
+            return id;
+        }
+        let id = self.next_id;
+        self.ids.insert(path.to_string(), id);
+        self.next_id += 1;
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_stable_and_never_reused() {
+        let mut ids = FieldIds::default();
+        assert_eq!(ids.id_for("status"), 0);
+        assert_eq!(ids.id_for("geo.lat"), 1);
+        assert_eq!(ids.id_for("status"), 0);
+        ids.ids.remove("status");
+        assert_eq!(ids.id_for("region"), 2);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut ids = FieldIds::default();
+        ids.id_for("event");
+        ids.id_for("magnitude");
+        let restored = FieldIds::from_json(&ids.to_json().unwrap()).unwrap();
+        assert_eq!(restored, ids);
+        assert!(FieldIds::from_json(b"[]").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Path-based flattening of nested JSON into leaf fields.
+
+use serde_json::{Map, Value};
+
+/// A leaf produced by [`flatten_object`].
+#[derive(Debug)]
+pub(crate) struct Leaf<'a> {
+    pub path: String,
+    pub value: &'a Value,
+    /// Nesting depth of the leaf; top-level keys are depth 1.
+    pub depth: usize,
+}
+
+/// Flatten a JSON object into leaves in document order.
+///
+/// Nested objects extend the path with `.key` and arrays with `[index]`, so
+/// `{"geo":{"lat":1},"items":[{"sku":"a"}]}` yields `geo.lat` and
+/// `items[0].sku`. Containers deeper than `max_depth` levels and empty
+/// containers are kept whole as a single leaf, as are all arrays unless
+/// `descend_arrays` is set.
+pub(crate) fn flatten_object(
+    obj: &Map<String, Value>,
+    max_depth: usize,
+    descend_arrays: bool,
+) -> Vec<Leaf<'_>> {
+    let mut leaves = Vec::new();
+    for (key, value) in obj {
+        flatten_value(
+            key.clone(),
+            value,
+            1,
+            max_depth,
+            descend_arrays,
+            &mut leaves,
+        );
+    }
+    leaves
+}
+
+fn flatten_value<'a>(
+    path: String,
+    value: &'a Value,
+    depth: usize,
+    max_depth: usize,
+    descend_arrays: bool,
+    leaves: &mut Vec<Leaf<'a>>,
+) {
+    if depth >= max_depth {
+        leaves.push(Leaf { path, value, depth });
+        return;
+    }
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, child) in map {
+                let path = format!("{path}.{key}");
+                flatten_value(path, child, depth + 1, max_depth, descend_arrays, leaves);
+            }
+        }
+        Value::Array(items) if descend_arrays && !items.is_empty() => {
+            for (i, child) in items.iter().enumerate() {
+                let path = format!("{path}[{i}]");
+                flatten_value(path, child, depth + 1, max_depth, descend_arrays, leaves);
+            }
+        }
+        _ => leaves.push(Leaf { path, value, depth }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paths(value: &Value, max_depth: usize) -> Vec<String> {
+        flatten_object(value.as_object().unwrap(), max_depth, true)
+            .into_iter()
+            .map(|leaf| leaf.path)
+            .collect()
+    }
+
+    #[test]
+    fn test_flatten_nested_objects_and_arrays() {
+        let value = json!({"geo":{"lat":1,"lon":2},"items":[{"sku":"a"},{"sku":"b"}],"ok":true});
+        let mut got = paths(&value, 8);
+        got.sort();
+        assert_eq!(
+            got,
+            ["geo.lat", "geo.lon", "items[0].sku", "items[1].sku", "ok"]
+        );
+    }
+
+    #[test]
+    fn test_flatten_stops_at_max_depth() {
+        let value = json!({"a":{"b":{"c":1}}});
+        assert_eq!(paths(&value, 2), ["a.b"]);
+        let leaves = flatten_object(value.as_object().unwrap(), 2, true);
+        assert_eq!(leaves[0].value, &json!({"c":1}));
+        assert_eq!(leaves[0].depth, 2);
+        assert_eq!(paths(&value, 1), ["a"]);
+    }
+
+    #[test]
+    fn test_flatten_can_keep_arrays_whole() {
+        let value = json!({"items":[{"sku":"a"}],"geo":{"lat":1}});
+        let mut got = paths(&value, 8);
+        got.sort();
+        assert_eq!(got, ["geo.lat", "items[0].sku"]);
+        let leaves = flatten_object(value.as_object().unwrap(), 8, false);
+        let mut got: Vec<&str> = leaves.iter().map(|l| l.path.as_str()).collect();
+        got.sort();
+        assert_eq!(got, ["geo.lat", "items"]);
+    }
+
+    #[test]
+    fn test_flatten_keeps_empty_containers_as_leaves() {
+        let value = json!({"tags":[],"meta":{}});
+        let mut got = paths(&value, 8);
+        got.sort();
+        assert_eq!(got, ["meta", "tags"]);
+    }
+}
@@ -0,0 +1,143 @@
+//! Structure-preserving encoding of nested JSON as role-filler records.
+//!
+//! An object is the bundle of `role(key) ⊙ filler` over its entries, where a
+//! filler is either an encoded scalar or, recursively, another record. Arrays
+//! are records keyed by `[index]` in [`ArrayMode::Indexed`], otherwise
+//! sequences or sets of element records (see [`crate::sequence`]). Because
+//! role vectors are dense bipolar, binding is self-inverse: unbinding a
+//! record with `role("geo")` yields the sub-record stored under `geo`, which
+//! can in turn be unbound further.
+
+use crate::types::{tag_leaf, JsonType};
+use crate::{
+    encode_scalar, encode_value, sequence, symbols::role_vector, ArrayMode, EncodedField,
+    EncoderConfig,
+};
+use embeddenator_vsa::SparseVec;
+use serde_json::{Map, Value};
+
+/// Role label for position `i` of an array.
+pub(crate) fn index_role(i: usize) -> String {
+    format!("[{i}]")
+}
+
+/// Encode `value`, found at `path` and nesting `depth`, as a record.
+/// Containers at `depth >= max_depth` are encoded whole with
+/// [`encode_value`] and tagged with their type; scalars with
+/// [`encode_scalar`].
+pub(crate) fn encode_record(
+    value: &Value,
+    path: &str,
+    depth: usize,
+    encoder: &EncoderConfig,
+) -> SparseVec {
+    if depth >= encoder.max_depth && (value.is_object() || value.is_array()) {
+        return tag_leaf(JsonType::of(value), &encode_value(value));
+    }
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            let entries: Vec<SparseVec> = map
+                .iter()
+                .map(|(key, child)| (key, child, format!("{path}.{key}")))
+                .filter(|(_, _, child_path)| !encoder.excludes(child_path))
+                .map(|(key, child, child_path)| {
+                    role_vector(key).bind(&encode_record(child, &child_path, depth + 1, encoder))
+                })
+                .collect();
+            SparseVec::bundle_sum_many(&entries)
+        }
+        Value::Array(items) if !items.is_empty() => {
+            let elements: Vec<SparseVec> = items
+                .iter()
+                .enumerate()
+                .map(|(i, child)| {
+                    let child_path = format!("{path}[{i}]");
+                    encode_record(child, &child_path, depth + 1, encoder)
+                })
+                .collect();
+            match encoder.arrays {
+                ArrayMode::Indexed => {
+                    let entries: Vec<SparseVec> = elements
+                        .iter()
+                        .enumerate()
+                        .map(|(i, element)| role_vector(&index_role(i)).bind(element))
+                        .collect();
+                    SparseVec::bundle_sum_many(&entries)
+                }
+                ArrayMode::Sequence => sequence::encode_sequence(&elements),
+                ArrayMode::Set => sequence::encode_set(&elements),
+            }
+        }
+        _ => encode_scalar(value, path, encoder),
+    }
+}
+
+/// Encode each top-level entry of `obj` selected by the encoder as
+/// `role(key) ⊙ record(value)`. `include` patterns apply to top-level keys;
+/// `exclude` patterns also drop entries nested inside records.
+pub(crate) fn encode_record_fields<'a>(
+    obj: &'a Map<String, Value>,
+    encoder: &EncoderConfig,
+) -> Vec<EncodedField<'a>> {
+    obj.iter()
+        .filter(|(key, _)| encoder.selects(key))
+        .map(|(key, value)| EncodedField {
+            path: key.clone(),
+            bound: role_vector(key).bind(&encode_record(value, key, 1, encoder)),
+            value,
+        })
+        .collect()
+}
+
+/// Unbind `path` (outermost key first) from a record, returning an
+/// approximation of the filler stored there. Array positions are addressed
+/// as `"[0]"`, `"[1]"`, ...
+pub(crate) fn unbind_path(record: &SparseVec, path: &[&str]) -> SparseVec {
+    path.iter()
+        .fold(record.clone(), |acc, key| role_vector(key).bind(&acc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn encode(value: &Value, depth: usize) -> SparseVec {
+        encode_record(value, "", depth, &EncoderConfig::default())
+    }
+
+    #[test]
+    fn test_unbinding_parent_key_recovers_sub_record() {
+        let value = json!({"geo":{"lat":"north","lon":"west"},"event":"quake"});
+        let record = encode(&value, 0);
+        let geo = unbind_path(&record, &["geo"]);
+        let expected = encode(&json!({"lat":"north","lon":"west"}), 1);
+        let other = encode(&json!({"lat":"south","lon":"east"}), 1);
+        assert!(geo.cosine(&expected) > 0.5);
+        assert!(geo.cosine(&expected) > geo.cosine(&other));
+    }
+
+    #[test]
+    fn test_unbinding_nested_path_recovers_leaf() {
+        let value = json!({"geo":{"lat":"north","lon":"west"}});
+        let record = encode(&value, 0);
+        let lat = unbind_path(&record, &["geo", "lat"]);
+        let north = encode_scalar(&json!("north"), "geo.lat", &EncoderConfig::default());
+        let west = encode_scalar(&json!("west"), "geo.lat", &EncoderConfig::default());
+        assert!(lat.cosine(&north) > lat.cosine(&west));
+    }
+
+    #[test]
+    fn test_record_fields_are_one_per_top_level_key() {
+        let value = json!({"geo":{"lat":1,"lon":2},"items":[{"sku":"a"}],"ok":true});
+        let fields = encode_record_fields(value.as_object().unwrap(), &EncoderConfig::default());
+        let mut names: Vec<&str> = fields.iter().map(|f| f.path.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["geo", "items", "ok"]);
+        let items = fields.iter().find(|f| f.path == "items").unwrap();
+        assert_eq!(JsonType::of(items.value), JsonType::Array);
+        let sku = unbind_path(&items.bound, &["items", &index_role(0), "sku"]);
+        let expected = encode_scalar(&json!("a"), "items[0].sku", &EncoderConfig::default());
+        assert!(sku.cosine(&expected) > 0.5);
+    }
+}
@@ -0,0 +1,294 @@
+//! Searchable per-subject history of message and field vectors.
+//!
+//! Every message of a subject adds its message bundle and its field vectors
+//! to the subject's history, stored under `history:v2:{subject}` as bincode.
+//! Only the newest `history_limit` messages are kept.
+//!
+//! The history is a ternary inverted index, laid out like
+//! `embeddenator_retrieval`'s `TernaryInvertedIndex` but serialisable: for
+//! the message bundles and for each field it lists, per dimension, the
+//! messages whose vector holds `+1` or `-1` there. Recording a message
+//! appends it to the lists of its non-zero dimensions and trims expired
+//! messages from the front of the lists, so the index is never rebuilt. A
+//! search reads only the lists of the query's dimensions, which give the
+//! exact ternary dot product, and hence the cosine, of every message that
+//! shares a dimension with the query.
+
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Default number of messages kept per subject.
+pub(crate) const DEFAULT_HISTORY_LIMIT: usize = 256;
+
+/// A search hit.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct HistoryMatch {
+    pub message: u64,
+    pub at_ms: u64,
+    pub field: Option<usize>,
+    /// Cosine similarity to the query.
+    pub score: f64,
+}
+
+/// Inverted index over the vectors of one scope: the message bundles, or
+/// the vectors of one field.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Postings {
+    /// Messages holding `+1` at each dimension, oldest first.
+    pos: BTreeMap<usize, Vec<u64>>,
+    /// Messages holding `-1` at each dimension, oldest first.
+    neg: BTreeMap<usize, Vec<u64>>,
+    /// Non-zero count of each message's vector, for the cosine norm.
+    nnz: BTreeMap<u64, usize>,
+}
+
+impl Postings {
+    /// Index `vector` under `message`, which is newer than every indexed
+    /// message.
+    fn add(&mut self, message: u64, vector: &SparseVec) {
+        for &dim in &vector.pos {
+            self.pos.entry(dim).or_default().push(message);
+        }
+        for &dim in &vector.neg {
+            self.neg.entry(dim).or_default().push(message);
+        }
+        self.nnz
+            .insert(message, vector.pos.len() + vector.neg.len());
+    }
+
+    /// Drop messages older than `oldest_kept`.
+    fn evict(&mut self, oldest_kept: u64) {
+        if self.nnz.keys().next().is_none_or(|&m| m >= oldest_kept) {
+            return;
+        }
+        self.nnz = self.nnz.split_off(&oldest_kept);
+        for lists in [&mut self.pos, &mut self.neg] {
+            lists.retain(|_, list| {
+                let expired = list.partition_point(|&m| m < oldest_kept);
+                list.drain(..expired);
+                !list.is_empty()
+            });
+        }
+    }
+
+    /// Messages sharing a dimension with `query`, by cosine similarity.
+    fn scores(&self, query: &SparseVec) -> Vec<(u64, f64)> {
+        let mut dots: BTreeMap<u64, i64> = BTreeMap::new();
+        for (dims, same, opposite) in [
+            (&query.pos, &self.pos, &self.neg),
+            (&query.neg, &self.neg, &self.pos),
+        ] {
+            for dim in dims {
+                for &message in same.get(dim).into_iter().flatten() {
+                    *dots.entry(message).or_default() += 1;
+                }
+                for &message in opposite.get(dim).into_iter().flatten() {
+                    *dots.entry(message).or_default() -= 1;
+                }
+            }
+        }
+        let query_nnz = query.pos.len() + query.neg.len();
+        dots.into_iter()
+            .filter_map(|(message, dot)| {
+                let norm = ((self.nnz.get(&message)? * query_nnz) as f64).sqrt();
+                Some((message, dot as f64 / norm))
+            })
+            .collect()
+    }
+
+    /// The vector indexed under `message`, read back from the lists.
+    fn vector(&self, message: u64) -> Option<SparseVec> {
+        self.nnz.contains_key(&message).then(|| {
+            let dims = |lists: &BTreeMap<usize, Vec<u64>>| -> Vec<usize> {
+                lists
+                    .iter()
+                    .filter(|(_, list)| list.binary_search(&message).is_ok())
+                    .map(|(dim, _)| *dim)
+                    .collect()
+            };
+            SparseVec {
+                pos: dims(&self.pos),
+                neg: dims(&self.neg),
+            }
+        })
+    }
+}
+
+/// The stored history of one subject.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct History {
+    /// Sequence number given to the next recorded message.
+    pub next_message: u64,
+    /// When each kept message was recorded, as Unix epoch milliseconds.
+    pub recorded: BTreeMap<u64, u64>,
+    /// Index of the message bundles (`None`) and of each field's vectors
+    /// (see [`crate::field_ids`]).
+    scopes: BTreeMap<Option<usize>, Postings>,
+}
+
+impl History {
+    /// Parse a history read from the bucket.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes).map_err(|e| format!("history decode error: {e}"))
+    }
+
+    /// Serialise the history for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("history encode error: {e}"))
+    }
+
+    /// Index a message bundle and its field vectors, then drop the oldest
+    /// messages beyond `limit`. Returns the message's sequence number.
+    pub(crate) fn record(
+        &mut self,
+        bundle: &SparseVec,
+        fields: &HashMap<usize, SparseVec>,
+        at_ms: u64,
+        limit: usize,
+    ) -> u64 {
+        let message = self.next_message;
+        self.next_message += 1;
+        self.recorded.insert(message, at_ms);
+        self.scopes.entry(None).or_default().add(message, bundle);
+        for (id, vector) in fields {
+            self.scopes
+                .entry(Some(*id))
+                .or_default()
+                .add(message, vector);
+        }
+
+        let oldest_kept = self.next_message.saturating_sub(limit as u64);
+        self.recorded = self.recorded.split_off(&oldest_kept);
+        for postings in self.scopes.values_mut() {
+            postings.evict(oldest_kept);
+        }
+        self.scopes.retain(|_, postings| !postings.nnz.is_empty());
+        message
+    }
+
+    /// Number of messages currently kept.
+    pub(crate) fn message_count(&self) -> usize {
+        self.recorded.len()
+    }
+
+    /// The stored bundle of message `message`, if it is still kept.
+    pub(crate) fn message_bundle(&self, message: u64) -> Option<SparseVec> {
+        self.scopes.get(&None)?.vector(message)
+    }
+
+    /// The `k` messages whose bundle (`field` is `None`) or whose vector of
+    /// one field is most similar to `query`, best first.
+    pub(crate) fn search(
+        &self,
+        query: &SparseVec,
+        field: Option<usize>,
+        k: usize,
+    ) -> Vec<HistoryMatch> {
+        let Some(postings) = self.scopes.get(&field) else {
+            return Vec::new();
+        };
+        let mut scores = postings.scores(query);
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        scores.truncate(k);
+        scores
+            .into_iter()
+            .map(|(message, score)| HistoryMatch {
+                message,
+                at_ms: self.recorded.get(&message).copied().unwrap_or_default(),
+                field,
+                score,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::sparse_code;
+
+    fn fields(labels: &[&str]) -> HashMap<usize, SparseVec> {
+        labels
+            .iter()
+            .enumerate()
+            .map(|(id, label)| (id, sparse_code(label, 200)))
+            .collect()
+    }
+
+    #[test]
+    fn test_search_finds_similar_past_messages() {
+        let mut history = History::default();
+        for (at, label) in ["login", "logout", "payment"].iter().enumerate() {
+            history.record(&sparse_code(label, 200), &fields(&[label]), at as u64, 10);
+        }
+        let hits = history.search(&sparse_code("logout", 200), None, 2);
+        assert_eq!(hits[0].message, 1);
+        assert_eq!(hits[0].at_ms, 1);
+        assert!((hits[0].score - 1.0).abs() < 1e-9);
+        assert!(hits.iter().all(|hit| hit.field.is_none()));
+        let bundle = history.message_bundle(hits[0].message).unwrap();
+        assert_eq!(bundle.pos, sparse_code("logout", 200).pos);
+        assert_eq!(bundle.neg, sparse_code("logout", 200).neg);
+        assert!(history.message_bundle(3).is_none());
+    }
+
+    #[test]
+    fn test_scores_match_cosine() {
+        let mut history = History::default();
+        let (a, b) = (sparse_code("a", 400), sparse_code("b", 400));
+        history.record(&a.bundle(&b), &HashMap::new(), 0, 10);
+        let hits = history.search(&a, None, 1);
+        assert!((hits[0].score - a.cosine(&a.bundle(&b))).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_search_scoped_to_one_field() {
+        let mut history = History::default();
+        history.record(&sparse_code("m0", 200), &fields(&["a", "b"]), 0, 10);
+        history.record(&sparse_code("m1", 200), &fields(&["c", "a"]), 0, 10);
+        // Field 0 of message 0 is also "a", but only field 1 is searched.
+        let hits = history.search(&sparse_code("a", 200), Some(1), 5);
+        assert_eq!(hits[0].message, 1);
+        assert!((hits[0].score - 1.0).abs() < 1e-9);
+        assert!(hits.iter().all(|hit| hit.field == Some(1)));
+        assert!(history
+            .search(&sparse_code("a", 200), Some(7), 5)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_oldest_messages_are_dropped() {
+        let mut history = History::default();
+        for n in 0..5 {
+            history.record(&sparse_code(&format!("m{n}"), 50), &fields(&["x"]), n, 3);
+        }
+        assert_eq!(history.message_count(), 3);
+        assert_eq!(history.recorded.keys().next(), Some(&2));
+        assert_eq!(history.next_message, 5);
+        assert!(history.message_bundle(1).is_none());
+        let hits = history.search(&sparse_code("m1", 50), None, 5);
+        assert!(hits.iter().all(|hit| hit.message >= 2), "got {hits:?}");
+        // Expired messages are gone from the lists, not just hidden.
+        let postings = &history.scopes[&None];
+        assert!(postings
+            .pos
+            .values()
+            .chain(postings.neg.values())
+            .flatten()
+            .all(|&m| m >= 2));
+    }
+
+    #[test]
+    fn test_bincode_round_trip() {
+        let mut history = History::default();
+        history.record(&sparse_code("m", 50), &fields(&["x", "y"]), 7, 3);
+        let restored = History::from_bytes(&history.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.next_message, 1);
+        assert_eq!(restored.recorded, history.recorded);
+        assert_eq!(restored.scopes.len(), 3);
+        let hits = restored.search(&sparse_code("x", 200), Some(0), 1);
+        assert_eq!((hits[0].message, hits[0].at_ms), (0, 7));
+    }
+}
@@ -0,0 +1,384 @@
+//! Key schema for entries persisted in the vector bucket
+//! (`pattern-monitor-vectors` by default).
+
+use crate::window::WindowSize;
+
+/// Legacy, subject-agnostic semantic vectors: `semantic:v1:{field}`.
+///
+/// Every subject wrote to the same key, so fields with the same name on
+/// different subjects clobbered each other. Only read during migration.
+pub(crate) const PREFIX_SEMANTIC: &str = "semantic:v1";
+
+/// Subject-scoped semantic vectors: `semantic:v2:{tenant}:{subject}:{field}`.
+pub(crate) const PREFIX_SEMANTIC_V2: &str = "semantic:v2";
+
+/// Master bundle per subject: `bundle:v1:{subject}`.
+pub(crate) const PREFIX_BUNDLE: &str = "bundle:v1";
+
+/// Votes behind the master bundle (bincode) per subject:
+/// `bundle-votes:v1:{subject}`.
+pub(crate) const PREFIX_BUNDLE_VOTES: &str = "bundle-votes:v1";
+
+/// Master bundle metadata (JSON) per subject: `bundle-meta:v1:{subject}`.
+pub(crate) const PREFIX_BUNDLE_META: &str = "bundle-meta:v1";
+
+/// Field path → id dictionary (JSON) per subject: `field-ids:v1:{subject}`.
+pub(crate) const PREFIX_FIELD_IDS: &str = "field-ids:v1";
+
+/// Inverted index of recent message and field vectors (bincode) per
+/// subject: `history:v2:{subject}`. `history:v1` held the raw vectors and is
+/// no longer read.
+pub(crate) const PREFIX_HISTORY: &str = "history:v2";
+
+/// Observed field values (bincode) per subject: `codebook:v1:{subject}`.
+pub(crate) const PREFIX_CODEBOOK: &str = "codebook:v1";
+
+/// Time-windowed bundles (bincode) per subject:
+/// `bundle-window:v1:{subject}:{size}:{start}`.
+pub(crate) const PREFIX_WINDOW: &str = "bundle-window:v1";
+
+/// Index (JSON) of the window bundles stored per subject:
+/// `bundle-windows:v1:{subject}`.
+pub(crate) const PREFIX_WINDOW_INDEX: &str = "bundle-windows:v1";
+
+/// Exponentially decaying baseline accumulator (bincode) per subject:
+/// `bundle-decay:v1:{subject}`.
+pub(crate) const PREFIX_DECAY: &str = "bundle-decay:v1";
+
+/// Drift events (JSON) per subject, kept for audit:
+/// `drift:v1:{subject}:{start}`.
+pub(crate) const PREFIX_DRIFT: &str = "drift:v1";
+
+/// Pattern prototypes (bincode) per subject: `prototypes:v2:{subject}`.
+pub(crate) const PREFIX_PROTOTYPES: &str = "prototypes:v2";
+
+/// Recent messages and known sequence patterns (bincode) per subject:
+/// `sequences:v1:{subject}`.
+pub(crate) const PREFIX_SEQUENCES: &str = "sequences:v1";
+
+/// Registry (JSON) of every subject with a master bundle: `subjects:v1`.
+pub(crate) const KEY_SUBJECTS: &str = "subjects:v1";
+
+/// Tenant segment used when no tenant is configured.
+pub(crate) const DEFAULT_TENANT: &str = "default";
+
+/// Subject segment assigned to `semantic:v1` vectors during migration, since
+/// the subject that produced them was never recorded.
+pub(crate) const LEGACY_SUBJECT: &str = "_legacy";
+
+/// Marker written once `semantic:v1` keys have been migrated to `semantic:v2`.
+#[cfg(not(test))]
+pub(crate) const KEY_SEMANTIC_SCHEMA: &str = "schema:semantic";
+
+/// Marker written once window bundles stored before window indexes existed
+/// have been indexed.
+#[cfg(not(test))]
+pub(crate) const KEY_WINDOW_SCHEMA: &str = "schema:window-index";
+
+/// Key prefixes and tenant used to build keys. Defaults to the documented
+/// schema; deployments sharing a bucket can override the prefixes.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct KeySchema {
+    /// Prefix of semantic vector keys, [`PREFIX_SEMANTIC_V2`] by default.
+    pub semantic: String,
+    /// Prefix of master bundle keys, [`PREFIX_BUNDLE`] by default.
+    pub bundle: String,
+    /// Prefix of bundle vote keys, [`PREFIX_BUNDLE_VOTES`] by default.
+    pub bundle_votes: String,
+    /// Prefix of bundle metadata keys, [`PREFIX_BUNDLE_META`] by default.
+    pub bundle_meta: String,
+    /// Prefix of field id dictionary keys, [`PREFIX_FIELD_IDS`] by default.
+    pub field_ids: String,
+    /// Prefix of history keys, [`PREFIX_HISTORY`] by default.
+    pub history: String,
+    /// Prefix of codebook keys, [`PREFIX_CODEBOOK`] by default.
+    pub codebook: String,
+    /// Prefix of window bundle keys, [`PREFIX_WINDOW`] by default.
+    pub window: String,
+    /// Prefix of window index keys, [`PREFIX_WINDOW_INDEX`] by default.
+    pub window_index: String,
+    /// Prefix of decaying baseline keys, [`PREFIX_DECAY`] by default.
+    pub decay: String,
+    /// Prefix of drift event keys, [`PREFIX_DRIFT`] by default.
+    pub drift: String,
+    /// Prefix of prototype keys, [`PREFIX_PROTOTYPES`] by default.
+    pub prototypes: String,
+    /// Prefix of sequence store keys, [`PREFIX_SEQUENCES`] by default.
+    pub sequences: String,
+    /// Key of the subject registry, [`KEY_SUBJECTS`] by default.
+    pub subjects: String,
+    /// Tenant segment of semantic keys, [`DEFAULT_TENANT`] by default.
+    pub tenant: String,
+}
+
+impl Default for KeySchema {
+    fn default() -> Self {
+        Self {
+            semantic: PREFIX_SEMANTIC_V2.to_string(),
+            bundle: PREFIX_BUNDLE.to_string(),
+            bundle_votes: PREFIX_BUNDLE_VOTES.to_string(),
+            bundle_meta: PREFIX_BUNDLE_META.to_string(),
+            field_ids: PREFIX_FIELD_IDS.to_string(),
+            history: PREFIX_HISTORY.to_string(),
+            codebook: PREFIX_CODEBOOK.to_string(),
+            window: PREFIX_WINDOW.to_string(),
+            window_index: PREFIX_WINDOW_INDEX.to_string(),
+            decay: PREFIX_DECAY.to_string(),
+            drift: PREFIX_DRIFT.to_string(),
+            prototypes: PREFIX_PROTOTYPES.to_string(),
+            sequences: PREFIX_SEQUENCES.to_string(),
+            subjects: KEY_SUBJECTS.to_string(),
+            tenant: DEFAULT_TENANT.to_string(),
+        }
+    }
+}
+
+impl KeySchema {
+    /// Build the semantic key for `field` on `subject`. The tenant segment is
+    /// always present so keys have a fixed shape.
+    pub(crate) fn semantic_key(&self, subject: &str, field: &str) -> String {
+        format!("{}:{}:{subject}:{field}", self.semantic, self.tenant)
+    }
+
+    /// Build the master bundle key for `subject`.
+    pub(crate) fn bundle_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.bundle)
+    }
+
+    /// Subject of a master bundle key, or `None` if `key` is not one.
+    pub(crate) fn bundle_subject<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(self.bundle.as_str())?
+            .strip_prefix(':')
+            .filter(|subject| !subject.is_empty())
+    }
+
+    /// Build the bundle vote key for `subject`.
+    pub(crate) fn bundle_votes_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.bundle_votes)
+    }
+
+    /// Build the bundle metadata key for `subject`.
+    pub(crate) fn bundle_meta_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.bundle_meta)
+    }
+
+    /// Build the field id dictionary key for `subject`.
+    pub(crate) fn field_ids_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.field_ids)
+    }
+
+    /// Build the history key for `subject`.
+    pub(crate) fn history_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.history)
+    }
+
+    /// Build the codebook key for `subject`.
+    pub(crate) fn codebook_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.codebook)
+    }
+
+    /// Build the decaying baseline key for `subject`.
+    pub(crate) fn decay_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.decay)
+    }
+
+    /// Build the key of the `size` window of `subject` starting at `start_ms`.
+    pub(crate) fn window_key(&self, subject: &str, size: WindowSize, start_ms: u64) -> String {
+        format!("{}:{subject}:{}:{start_ms}", self.window, size.as_str())
+    }
+
+    /// Subject, size and start of a window key, or `None` if `key` is not
+    /// one.
+    pub(crate) fn window_of<'a>(&self, key: &'a str) -> Option<(&'a str, WindowSize, u64)> {
+        let rest = key.strip_prefix(self.window.as_str())?.strip_prefix(':')?;
+        let (rest, start) = rest.rsplit_once(':')?;
+        let (subject, size) = rest.rsplit_once(':')?;
+        if subject.is_empty() {
+            return None;
+        }
+        Some((subject, WindowSize::parse(size)?, start.parse().ok()?))
+    }
+
+    /// Build the window index key for `subject`.
+    pub(crate) fn window_index_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.window_index)
+    }
+
+    /// Build the prototypes key for `subject`.
+    pub(crate) fn prototypes_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.prototypes)
+    }
+
+    /// Build the sequence store key for `subject`.
+    pub(crate) fn sequences_key(&self, subject: &str) -> String {
+        format!("{}:{subject}", self.sequences)
+    }
+
+    /// Build the key of the drift event detected for `subject` when the
+    /// window starting at `start_ms` closed.
+    pub(crate) fn drift_key(&self, subject: &str, start_ms: u64) -> String {
+        format!("{}:{subject}:{start_ms}", self.drift)
+    }
+
+    /// Map a legacy `semantic:v1:{field}` key to the semantic key it is
+    /// migrated to, placing it under [`LEGACY_SUBJECT`].
+    pub(crate) fn migrated_semantic_key(&self, legacy_key: &str) -> Option<String> {
+        legacy_semantic_field(legacy_key).map(|field| self.semantic_key(LEGACY_SUBJECT, field))
+    }
+}
+
+/// Return the field name of a legacy `semantic:v1:{field}` key, or `None` if
+/// `key` belongs to another schema.
+pub(crate) fn legacy_semantic_field(key: &str) -> Option<&str> {
+    key.strip_prefix(PREFIX_SEMANTIC)?
+        .strip_prefix(':')
+        .filter(|field| !field.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenant(name: &str) -> KeySchema {
+        KeySchema {
+            tenant: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_semantic_key_is_scoped_by_subject() {
+        let keys = KeySchema::default();
+        let auth = keys.semantic_key("pattern.monitor.auth", "status");
+        let billing = keys.semantic_key("pattern.monitor.billing", "status");
+        assert_ne!(
+            auth, billing,
+            "same field on different subjects must not collide"
+        );
+        assert_eq!(auth, "semantic:v2:default:pattern.monitor.auth:status");
+    }
+
+    #[test]
+    fn test_semantic_key_includes_tenant() {
+        let key = tenant("acme").semantic_key("pattern.monitor.auth", "status");
+        assert_eq!(key, "semantic:v2:acme:pattern.monitor.auth:status");
+    }
+
+    #[test]
+    fn test_bundle_key_format() {
+        let keys = KeySchema::default();
+        assert_eq!(
+            keys.bundle_key("pattern.monitor.auth"),
+            "bundle:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.bundle_votes_key("pattern.monitor.auth"),
+            "bundle-votes:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.bundle_meta_key("pattern.monitor.auth"),
+            "bundle-meta:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.field_ids_key("pattern.monitor.auth"),
+            "field-ids:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.history_key("pattern.monitor.auth"),
+            "history:v2:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.codebook_key("pattern.monitor.auth"),
+            "codebook:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.decay_key("pattern.monitor.auth"),
+            "bundle-decay:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.prototypes_key("pattern.monitor.auth"),
+            "prototypes:v2:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.sequences_key("pattern.monitor.auth"),
+            "sequences:v1:pattern.monitor.auth"
+        );
+        assert_eq!(
+            keys.drift_key("pattern.monitor.auth", 3_600_000),
+            "drift:v1:pattern.monitor.auth:3600000"
+        );
+    }
+
+    #[test]
+    fn test_bundle_subject_parses_bundle_keys_only() {
+        let keys = KeySchema::default();
+        assert_eq!(
+            keys.bundle_subject("bundle:v1:pattern.monitor.auth"),
+            Some("pattern.monitor.auth")
+        );
+        assert_eq!(
+            keys.bundle_subject("bundle-meta:v1:pattern.monitor.auth"),
+            None
+        );
+        assert_eq!(keys.bundle_subject("bundle:v1:"), None);
+    }
+
+    #[test]
+    fn test_window_keys_round_trip() {
+        let keys = KeySchema::default();
+        let key = keys.window_key("orders", WindowSize::Hour, 1_760_486_400_000);
+        assert_eq!(key, "bundle-window:v1:orders:hour:1760486400000");
+        assert_eq!(
+            keys.window_of(&key),
+            Some(("orders", WindowSize::Hour, 1_760_486_400_000))
+        );
+        assert_eq!(keys.window_of("bundle:v1:orders"), None);
+        assert_eq!(keys.window_of("bundle-window:v1:orders:week:0"), None);
+        assert_eq!(keys.window_of("bundle-window:v1::hour:0"), None);
+        assert_eq!(
+            keys.window_of("bundle-window:v1:orders:eu:hour:0"),
+            Some(("orders:eu", WindowSize::Hour, 0))
+        );
+        assert_eq!(keys.bundle_subject(&key), None);
+        assert_eq!(keys.window_index_key("orders"), "bundle-windows:v1:orders");
+    }
+
+    #[test]
+    fn test_key_prefixes_are_configurable() {
+        let keys = KeySchema {
+            semantic: "stream-a:semantic".to_string(),
+            bundle: "stream-a:bundle".to_string(),
+            bundle_meta: "stream-a:meta".to_string(),
+            field_ids: "stream-a:ids".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(keys.semantic_key("s", "f"), "stream-a:semantic:default:s:f");
+        assert_eq!(keys.bundle_key("s"), "stream-a:bundle:s");
+        assert_eq!(keys.bundle_meta_key("s"), "stream-a:meta:s");
+        assert_eq!(keys.field_ids_key("s"), "stream-a:ids:s");
+    }
+
+    #[test]
+    fn test_legacy_semantic_field_parses_v1_keys_only() {
+        assert_eq!(legacy_semantic_field("semantic:v1:event"), Some("event"));
+        assert_eq!(legacy_semantic_field("semantic:v1:"), None);
+        assert_eq!(legacy_semantic_field("semantic:v2:default:s:event"), None);
+        assert_eq!(
+            legacy_semantic_field("bundle:v1:pattern.monitor.auth"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_migrated_semantic_key_uses_legacy_subject() {
+        let key = KeySchema::default().migrated_semantic_key("semantic:v1:magnitude");
+        assert_eq!(
+            key.as_deref(),
+            Some("semantic:v2:default:_legacy:magnitude")
+        );
+        assert_eq!(
+            KeySchema::default().migrated_semantic_key("bundle:v1:x"),
+            None
+        );
+    }
+}
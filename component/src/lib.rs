@@ -2,11 +2,41 @@
 #[cfg(not(test))]
 wit_bindgen::generate!({ generate_all });
 
-use embeddenator_io::to_bincode;
-use embeddenator_retrieval::TernaryInvertedIndex;
+mod alert;
+mod anomaly;
+mod baseline;
+mod codebook;
+mod decay;
+mod drift;
+mod field_ids;
+mod flatten;
+mod hierarchy;
+mod history;
+mod keys;
+mod numeric;
+mod prototype;
+mod query;
+mod routes;
+mod schema;
+mod sequence;
+mod sequence_patterns;
+mod settings;
+mod symbols;
+mod text;
+mod timestamp;
+mod types;
+mod weights;
+mod window;
+
+use embeddenator_io::{from_bincode, to_bincode};
 use embeddenator_vsa::{ReversibleVSAConfig, SparseVec};
-use serde_json::Value;
-use std::collections::HashMap;
+use field_ids::FieldIds;
+use numeric::NumericRange;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use text::StringEncoding;
+use timestamp::TimestampFormat;
+use types::JsonType;
 
 // ─── Pure encoding logic (testable on native target) ─────────────────────────
 
@@ -14,47 +44,340 @@ use std::collections::HashMap;
 pub(crate) struct EncodedFields {
     pub id_to_vec: HashMap<usize, SparseVec>,
     pub id_to_field: HashMap<usize, String>,
-    pub index: TernaryInvertedIndex,
+    /// JSON type of each field's value.
+    pub id_to_type: HashMap<usize, JsonType>,
+    /// Each field's value as JSON text.
+    pub id_to_value: HashMap<usize, String>,
+}
+
+/// One encoded field of a message, before it is given an id.
+pub(crate) struct EncodedField<'a> {
+    pub path: String,
+    /// `role(path) ⊙ value vector`.
+    pub bound: SparseVec,
+    pub value: &'a Value,
+}
+
+/// Default nesting depth encoded by [`encode_json_fields`].
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// How nested objects and arrays are represented in field vectors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum StructureMode {
+    /// One field per leaf, named by its path (`geo.lat`).
+    #[default]
+    Flatten,
+    /// One field per top-level key; nested containers become role-filler
+    /// records (see [`hierarchy`]).
+    Hierarchical,
+}
+
+impl StructureMode {
+    /// Parse a mode name (`"flatten"` or `"hierarchical"`).
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "flatten" => Some(Self::Flatten),
+            "hierarchical" => Some(Self::Hierarchical),
+            _ => None,
+        }
+    }
+}
+
+/// How JSON arrays are represented.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ArrayMode {
+    /// Each element is addressed by index: `items[0]` paths when flattening,
+    /// `[0]` roles in records.
+    #[default]
+    Indexed,
+    /// The array is one value: an order-sensitive bundle of position-permuted
+    /// elements (see [`sequence::encode_sequence`]).
+    Sequence,
+    /// The array is one value: an order-insensitive bundle of its elements.
+    Set,
+}
+
+impl ArrayMode {
+    /// Parse a mode name (`"indexed"`, `"sequence"` or `"set"`).
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "indexed" => Some(Self::Indexed),
+            "sequence" => Some(Self::Sequence),
+            "set" => Some(Self::Set),
+            _ => None,
+        }
+    }
+}
+
+/// Options controlling how a JSON message is turned into field vectors.
+#[derive(Debug, Clone)]
+pub(crate) struct EncoderConfig {
+    /// Nesting levels flattened into separate leaf fields (or nested as
+    /// records); deeper containers are encoded whole.
+    pub max_depth: usize,
+    pub structure: StructureMode,
+    pub arrays: ArrayMode,
+    /// Numeric ranges by field path; other numbers use
+    /// [`NumericRange::default`].
+    pub numeric_ranges: HashMap<String, NumericRange>,
+    /// String encodings by field path; other strings use
+    /// [`StringEncoding::Exact`].
+    pub string_encodings: HashMap<String, StringEncoding>,
+    /// Fields whose numbers are encoded as exact values rather than levels.
+    pub categorical_fields: HashSet<String>,
+    /// Fields holding timestamps, by path, with their format.
+    pub timestamp_fields: HashMap<String, TimestampFormat>,
+    /// Treat any other RFC 3339 string as a timestamp. Off by default, as
+    /// turning it on changes how existing string fields are encoded.
+    pub detect_timestamps: bool,
+    /// Field path patterns to keep; all fields if empty (see
+    /// [`schema::path_matches`]).
+    pub include: Vec<String>,
+    /// Field path patterns to drop.
+    pub exclude: Vec<String>,
+    /// Weights of fields in the master bundle as `(pattern, weight)`, first
+    /// match wins (see [`weights::resolve_weights`]).
+    pub field_weights: Vec<(String, f64)>,
+    /// Learn weights of fields without a configured weight from how much
+    /// they vary against the baseline.
+    pub learn_weights: bool,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            structure: StructureMode::default(),
+            arrays: ArrayMode::default(),
+            numeric_ranges: HashMap::new(),
+            string_encodings: HashMap::new(),
+            categorical_fields: HashSet::new(),
+            timestamp_fields: HashMap::new(),
+            detect_timestamps: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            field_weights: Vec::new(),
+            learn_weights: false,
+        }
+    }
+}
+
+impl EncoderConfig {
+    /// Whether the field at `path` is encoded: it matches an `include`
+    /// pattern (or there are none) and no `exclude` pattern.
+    pub(crate) fn selects(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| schema::path_matches(p, path)))
+            && !self.excludes(path)
+    }
+
+    /// Whether the field at `path` matches an `exclude` pattern.
+    pub(crate) fn excludes(&self, path: &str) -> bool {
+        self.exclude.iter().any(|p| schema::path_matches(p, path))
+    }
+}
+
+/// Parse a JSON object and encode each field as a bound VSA hypervector
+/// (dense path role ⊙ encoded value). Returns `Err` if the payload is not a
+/// valid JSON object.
+///
+/// In [`StructureMode::Flatten`] nested objects and arrays are flattened to
+/// path-named leaves (`geo.lat`, `items[0].sku`); in
+/// [`StructureMode::Hierarchical`] see [`hierarchy::encode_record_fields`].
+/// Fields not selected by [`EncoderConfig::selects`] are skipped. Field ids
+/// come from `field_ids`, which learns any new paths.
+pub(crate) fn encode_json_fields(
+    body: &[u8],
+    encoder: &EncoderConfig,
+    field_ids: &mut FieldIds,
+) -> Result<EncodedFields, String> {
+    let obj = parse_json_object(body)?;
+    let fields: Vec<EncodedField> = match encoder.structure {
+        StructureMode::Flatten => {
+            let descend_arrays = encoder.arrays == ArrayMode::Indexed;
+            flatten::flatten_object(&obj, encoder.max_depth, descend_arrays)
+                .into_iter()
+                .filter(|leaf| encoder.selects(&leaf.path))
+                .map(|leaf| {
+                    // Arrays kept whole are sequences/sets of element records.
+                    let value_vec =
+                        hierarchy::encode_record(leaf.value, &leaf.path, leaf.depth, encoder);
+                    let bound = symbols::role_vector(&leaf.path).bind(&value_vec);
+                    EncodedField {
+                        path: leaf.path,
+                        bound,
+                        value: leaf.value,
+                    }
+                })
+                .collect()
+        }
+        StructureMode::Hierarchical => hierarchy::encode_record_fields(&obj, encoder),
+    };
+    Ok(collect_fields(fields, field_ids))
 }
 
-/// Parse a JSON object and encode each key/value field as a bound VSA
-/// hypervector. Returns `Err` if the payload is not a valid JSON object.
-pub(crate) fn encode_json_fields(body: &[u8]) -> Result<EncodedFields, String> {
+/// Parse `body` as a JSON object.
+pub(crate) fn parse_json_object(body: &[u8]) -> Result<Map<String, Value>, String> {
     let json: Value = serde_json::from_slice(body).map_err(|e| format!("JSON parse error: {e}"))?;
+    match json {
+        Value::Object(obj) => Ok(obj),
+        _ => Err("message body is not a JSON object".to_string()),
+    }
+}
 
-    let obj = json
-        .as_object()
-        .ok_or_else(|| "message body is not a JSON object".to_string())?;
+/// Encode a leaf value found at `path` with the encoder for its JSON type,
+/// tagged with that type (see [`types::tag_leaf`]):
+///
+/// - timestamps (configured fields, or detected RFC 3339 strings): cyclic
+///   [`timestamp`] features;
+/// - numbers: similarity-preserving [`numeric`] encoding with the field's
+///   configured range, or exact bytes for categorical fields;
+/// - strings: the raw string bytes (no JSON quoting), or word and n-gram
+///   [`text`] symbols for fields configured with [`StringEncoding::Text`];
+/// - booleans and null: fixed sparse symbols;
+/// - containers kept whole (beyond `max_depth`): their serialised JSON.
+pub(crate) fn encode_scalar(value: &Value, path: &str, encoder: &EncoderConfig) -> SparseVec {
+    let format = encoder.timestamp_fields.get(path).copied();
+    if let Some(seconds) = timestamp::local_seconds(value, format, encoder.detect_timestamps) {
+        let content = timestamp::encode_timestamp(seconds, path);
+        return types::tag_leaf(JsonType::of(value), &content);
+    }
+    let config = ReversibleVSAConfig::default();
+    let content = match value {
+        Value::Null => symbols::sparse_code("null", 16),
+        Value::Bool(b) => symbols::sparse_code(if *b { "bool:true" } else { "bool:false" }, 16),
+        Value::Number(n) if encoder.categorical_fields.contains(path) => {
+            SparseVec::encode_data(n.to_string().as_bytes(), &config, None)
+        }
+        Value::Number(n) => {
+            let default_range = NumericRange::default();
+            let range = encoder.numeric_ranges.get(path).unwrap_or(&default_range);
+            numeric::encode_number(n.as_f64().unwrap_or_default(), range, path)
+        }
+        Value::String(s) => match encoder.string_encodings.get(path) {
+            Some(StringEncoding::Text) => text::encode_text(s),
+            _ => SparseVec::encode_data(s.as_bytes(), &config, None),
+        },
+        Value::Array(_) | Value::Object(_) => encode_value(value),
+    };
+    types::tag_leaf(JsonType::of(value), &content)
+}
 
+/// Encode a single JSON value (without its key) as a hypervector from its
+/// serialised bytes.
+pub(crate) fn encode_value(value: &Value) -> SparseVec {
     // ReversibleVSAConfig::default() is fully deterministic (no random state).
     let config = ReversibleVSAConfig::default();
-    let mut id_to_vec: HashMap<usize, SparseVec> = HashMap::new();
-    let mut id_to_field: HashMap<usize, String> = HashMap::new();
-    let mut index = TernaryInvertedIndex::new();
-
-    for (idx, (key, value)) in obj.iter().enumerate() {
-        let key_vec = SparseVec::encode_data(key.as_bytes(), &config, None);
-        let val_vec = SparseVec::encode_data(value.to_string().as_bytes(), &config, None);
-        let bound = key_vec.bind(&val_vec);
-        index.add(idx, &bound);
-        id_to_field.insert(idx, key.clone());
-        id_to_vec.insert(idx, bound);
-    }
-
-    index.finalize();
-    Ok(EncodedFields {
-        id_to_vec,
-        id_to_field,
-        index,
-    })
+    SparseVec::encode_data(value.to_string().as_bytes(), &config, None)
+}
+
+/// Look up the id of each bound field vector. New paths get ids in path
+/// order, so the first message of a subject numbers its fields the same way
+/// whatever its key order.
+fn collect_fields(mut fields: Vec<EncodedField>, field_ids: &mut FieldIds) -> EncodedFields {
+    let mut encoded = EncodedFields {
+        id_to_vec: HashMap::new(),
+        id_to_field: HashMap::new(),
+        id_to_type: HashMap::new(),
+        id_to_value: HashMap::new(),
+    };
+
+    fields.sort_by(|a, b| a.path.cmp(&b.path));
+    for field in fields {
+        let idx = field_ids.id_for(&field.path);
+        encoded.id_to_type.insert(idx, JsonType::of(field.value));
+        encoded.id_to_value.insert(idx, field.value.to_string());
+        encoded.id_to_field.insert(idx, field.path);
+        encoded.id_to_vec.insert(idx, field.bound);
+    }
+    encoded
 }
 
 /// Bundle all per-field hypervectors into a single master bundle vector via
-/// VSA superposition. Returns `None` if `id_to_vec` is empty.
-pub(crate) fn build_master_bundle(id_to_vec: &HashMap<usize, SparseVec>) -> Option<SparseVec> {
-    let mut iter = id_to_vec.values();
-    iter.next()
-        .map(|first| iter.fold(first.clone(), |acc, v| acc.bundle(v)))
+/// weighted VSA superposition (see [`weights::weighted_bundle`]). Fields
+/// missing from `weights` have weight 1. Returns `None` if `id_to_vec` is
+/// empty or every field has weight 0.
+pub(crate) fn build_master_bundle(
+    id_to_vec: &HashMap<usize, SparseVec>,
+    weights: &HashMap<usize, f64>,
+) -> Option<SparseVec> {
+    // Sorted by id so vote ties resolve the same way on every run.
+    let mut ids: Vec<&usize> = id_to_vec.keys().collect();
+    ids.sort_unstable();
+    weights::weighted_bundle(
+        ids.into_iter()
+            .map(|id| (&id_to_vec[id], weights.get(id).copied().unwrap_or(1.0))),
+    )
+}
+
+/// A message encoded as it would be on a subject, for answering queries.
+pub(crate) struct EncodedExample {
+    pub fields: EncodedFields,
+    /// Weight of each field in `bundle`.
+    pub weights: HashMap<usize, f64>,
+    /// The message bundle; `None` if no field has positive weight.
+    pub bundle: Option<SparseVec>,
+}
+
+impl EncodedExample {
+    /// Per-field breakdown of how the example deviates from `baseline` (see
+    /// [`anomaly::field_deviations`]).
+    pub(crate) fn deviations(
+        &self,
+        baseline: &SparseVec,
+        baseline_types: &BTreeMap<String, JsonType>,
+    ) -> Vec<anomaly::FieldDeviation> {
+        anomaly::field_deviations(
+            &self.fields.id_to_vec,
+            &self.fields.id_to_field,
+            &self.fields.id_to_type,
+            &self.weights,
+            baseline,
+            baseline_types,
+        )
+    }
+}
+
+/// Encode `body` as it would be encoded on a subject with the given field
+/// ids and metadata, without recording new field ids.
+pub(crate) fn encode_example(
+    body: &[u8],
+    encoder: &EncoderConfig,
+    field_ids: &FieldIds,
+    meta: &baseline::BundleMeta,
+) -> Result<EncodedExample, String> {
+    let fields = encode_json_fields(body, encoder, &mut field_ids.clone())?;
+    let weights = weights::resolve_weights(
+        &fields.id_to_field,
+        &encoder.field_weights,
+        encoder.learn_weights.then_some(&meta.field_variability),
+    );
+    let bundle = build_master_bundle(&fields.id_to_vec, &weights);
+    Ok(EncodedExample {
+        fields,
+        weights,
+        bundle,
+    })
+}
+
+/// Score `body` against a subject's `baseline` without folding it in, with
+/// every field's deviation. `Ok(None)` if no field has positive weight.
+pub(crate) fn score_example(
+    subject: &str,
+    body: &[u8],
+    encoder: &EncoderConfig,
+    field_ids: &FieldIds,
+    meta: &baseline::BundleMeta,
+    baseline: &SparseVec,
+) -> Result<Option<query::ScoreResponse>, String> {
+    let example = encode_example(body, encoder, field_ids, meta)?;
+    let Some(bundle) = &example.bundle else {
+        return Ok(None);
+    };
+    Ok(Some(query::ScoreResponse {
+        subject: subject.to_string(),
+        score: anomaly::score_against_baseline(bundle, baseline),
+        fields: example.deviations(baseline, &meta.field_types),
+    }))
 }
 
 /// Serialise a `SparseVec` to bincode bytes.
@@ -62,14 +385,24 @@ pub(crate) fn serialise_vector(vec: &SparseVec) -> Result<Vec<u8>, String> {
     to_bincode(vec).map_err(|e| format!("bincode encode error: {e}"))
 }
 
+/// Deserialise a `SparseVec` from bincode bytes.
+pub(crate) fn deserialise_vector(bytes: &[u8]) -> Result<SparseVec, String> {
+    from_bincode(bytes).map_err(|e| format!("bincode decode error: {e}"))
+}
+
 // ─── wasmCloud component implementation (excluded from test builds) ───────────
 
+/// Load [`settings::Settings`] from the component's runtime config.
 #[cfg(not(test))]
-const BUCKET_ID: &str = "pattern-monitor-vectors";
-#[cfg(not(test))]
-const PREFIX_SEMANTIC: &str = "semantic:v1";
-#[cfg(not(test))]
-const PREFIX_BUNDLE: &str = "bundle:v1";
+fn load_settings() -> Result<settings::Settings, String> {
+    use crate::wasi::config::runtime::{self, ConfigError};
+
+    let pairs = runtime::get_all().map_err(|e| match e {
+        ConfigError::Upstream(msg) => format!("config error (upstream): {msg}"),
+        ConfigError::Io(msg) => format!("config error (io): {msg}"),
+    })?;
+    settings::Settings::from_pairs(pairs)
+}
 
 #[cfg(not(test))]
 fn kv_err(e: crate::wasi::keyvalue::store::Error) -> String {
@@ -81,6 +414,337 @@ fn kv_err(e: crate::wasi::keyvalue::store::Error) -> String {
     }
 }
 
+/// Every key in `bucket`, following list cursors to the end.
+#[cfg(not(test))]
+fn list_all_keys(bucket: &crate::wasi::keyvalue::store::Bucket) -> Result<Vec<String>, String> {
+    let mut all = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = bucket.list_keys(cursor.as_deref()).map_err(kv_err)?;
+        all.extend(page.keys);
+        match page.cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(all),
+        }
+    }
+}
+
+/// How long a schema read from the bucket is reused before it is read
+/// again, in milliseconds.
+#[cfg(not(test))]
+const STORED_SCHEMA_REFRESH_MS: u64 = 60_000;
+
+/// The stored schema as last read by this instance: its key, when it was
+/// read, and the schema, if the key held one.
+#[cfg(not(test))]
+static STORED_SCHEMA: std::sync::Mutex<Option<(String, u64, Option<schema::Schema>)>> =
+    std::sync::Mutex::new(None);
+
+/// Field schema stored in the bucket under `schema_key`, if configured.
+/// Each instance reads it at most once every [`STORED_SCHEMA_REFRESH_MS`].
+#[cfg(not(test))]
+fn load_stored_schema(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    settings: &settings::Settings,
+) -> Result<Option<schema::Schema>, String> {
+    let Some(key) = &settings.schema_key else {
+        return Ok(None);
+    };
+    let now = now_millis();
+    let mut cached = STORED_SCHEMA.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_key, read_ms, schema)) = cached.as_ref() {
+        if cached_key == key && now.saturating_sub(*read_ms) < STORED_SCHEMA_REFRESH_MS {
+            return Ok(schema.clone());
+        }
+    }
+    let schema = bucket
+        .get(key)
+        .map_err(kv_err)?
+        .map(|bytes| schema::Schema::parse(&String::from_utf8_lossy(&bytes)))
+        .transpose()?;
+    *cached = Some((key.clone(), now, schema.clone()));
+    Ok(schema)
+}
+
+/// Whether this instance has seen [`keys::KEY_SEMANTIC_SCHEMA`]; once set,
+/// the bucket is not asked again.
+#[cfg(not(test))]
+static SEMANTIC_KEYS_MIGRATED: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
+/// Move any `semantic:v1:{field}` keys to `semantic:v2` under
+/// [`keys::LEGACY_SUBJECT`]. Runs once per bucket, guarded by
+/// [`keys::KEY_SEMANTIC_SCHEMA`], and checks the guard once per instance.
+#[cfg(not(test))]
+fn migrate_legacy_semantic_keys(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    key_schema: &keys::KeySchema,
+) -> Result<usize, String> {
+    use std::sync::atomic::Ordering;
+
+    if SEMANTIC_KEYS_MIGRATED.load(Ordering::Relaxed) {
+        return Ok(0);
+    }
+    if bucket.exists(keys::KEY_SEMANTIC_SCHEMA).map_err(kv_err)? {
+        SEMANTIC_KEYS_MIGRATED.store(true, Ordering::Relaxed);
+        return Ok(0);
+    }
+
+    let legacy_keys: Vec<String> = list_all_keys(bucket)?
+        .into_iter()
+        .filter(|k| keys::legacy_semantic_field(k).is_some())
+        .collect();
+
+    for legacy_key in &legacy_keys {
+        if let (Some(new_key), Some(bytes)) = (
+            key_schema.migrated_semantic_key(legacy_key),
+            bucket.get(legacy_key).map_err(kv_err)?,
+        ) {
+            bucket.set(&new_key, &bytes).map_err(kv_err)?;
+        }
+        bucket.delete(legacy_key).map_err(kv_err)?;
+    }
+
+    bucket
+        .set(keys::KEY_SEMANTIC_SCHEMA, key_schema.semantic.as_bytes())
+        .map_err(kv_err)?;
+    SEMANTIC_KEYS_MIGRATED.store(true, Ordering::Relaxed);
+    Ok(legacy_keys.len())
+}
+
+/// Current wall-clock time as Unix epoch milliseconds.
+#[cfg(not(test))]
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Publish `body` on `subject`.
+#[cfg(not(test))]
+fn publish(subject: &str, body: Vec<u8>) -> Result<(), String> {
+    use crate::wasmcloud::messaging::{consumer, types::BrokerMessage};
+
+    consumer::publish(&BrokerMessage {
+        subject: subject.to_string(),
+        body,
+        reply_to: None,
+    })
+}
+
+/// Read a value stored in `bucket` and parse it with `parse`, or `None` if
+/// the key is absent.
+#[cfg(not(test))]
+fn load<T>(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    key: &str,
+    parse: impl FnOnce(&[u8]) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    bucket
+        .get(key)
+        .map_err(kv_err)?
+        .map(|bytes| parse(&bytes))
+        .transpose()
+}
+
+/// Every subject with a stored master bundle, read from the subject
+/// registry. A bucket written before the registry existed is scanned once
+/// to create it.
+#[cfg(not(test))]
+fn list_subjects(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    key_schema: &keys::KeySchema,
+) -> Result<std::collections::BTreeSet<String>, String> {
+    let parse = |bytes: &[u8]| {
+        serde_json::from_slice(bytes).map_err(|e| format!("subject registry parse error: {e}"))
+    };
+    if let Some(subjects) = load(bucket, &key_schema.subjects, parse)? {
+        return Ok(subjects);
+    }
+    let subjects: std::collections::BTreeSet<String> = list_all_keys(bucket)?
+        .iter()
+        .filter_map(|key| key_schema.bundle_subject(key))
+        .map(str::to_string)
+        .collect();
+    save_subjects(bucket, key_schema, &subjects)?;
+    Ok(subjects)
+}
+
+/// Add `subject` to the subject registry once its first master bundle is
+/// stored.
+#[cfg(not(test))]
+fn register_subject(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    key_schema: &keys::KeySchema,
+    subject: &str,
+) -> Result<(), String> {
+    let mut subjects = list_subjects(bucket, key_schema)?;
+    if subjects.insert(subject.to_string()) {
+        save_subjects(bucket, key_schema, &subjects)?;
+    }
+    Ok(())
+}
+
+/// Store the subject registry.
+#[cfg(not(test))]
+fn save_subjects(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    key_schema: &keys::KeySchema,
+    subjects: &std::collections::BTreeSet<String>,
+) -> Result<(), String> {
+    let bytes =
+        serde_json::to_vec(subjects).map_err(|e| format!("subject registry encode error: {e}"))?;
+    bucket.set(&key_schema.subjects, &bytes).map_err(kv_err)
+}
+
+/// Score a message against its subject's baseline without folding it in.
+/// `Ok(None)` if the subject has no baseline or the message no weighted
+/// fields.
+#[cfg(not(test))]
+fn score_request(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    settings: &settings::Settings,
+    schema: Option<&schema::Schema>,
+    request: &query::ScoreRequest,
+) -> Result<Option<query::ScoreResponse>, String> {
+    use crate::baseline::BundleMeta;
+
+    let subject = &request.subject;
+    let Some(baseline) = load_baseline(bucket, settings, subject)? else {
+        return Ok(None);
+    };
+    let encoder = settings.encoder_for(schema, subject)?;
+    let field_ids = load(
+        bucket,
+        &settings.keys.field_ids_key(subject),
+        FieldIds::from_json,
+    )?
+    .unwrap_or_default();
+    let meta = load(
+        bucket,
+        &settings.keys.bundle_meta_key(subject),
+        BundleMeta::from_json,
+    )?
+    .unwrap_or_default();
+    score_example(
+        subject,
+        &request.message_body(),
+        &encoder,
+        &field_ids,
+        &meta,
+        &baseline,
+    )
+}
+
+/// The baseline messages of `subject` are scored against: its decaying
+/// baseline when `decay_half_life` is set and one exists, its master bundle
+/// otherwise.
+#[cfg(not(test))]
+fn load_baseline(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    settings: &settings::Settings,
+    subject: &str,
+) -> Result<Option<SparseVec>, String> {
+    if settings.decay_half_life > 0 {
+        let decaying = load(
+            bucket,
+            &settings.keys.decay_key(subject),
+            decay::DecayingBundle::from_bytes,
+        )?;
+        if let Some(decaying) = decaying.filter(|d| d.message_count > 0) {
+            return Ok(Some(decaying.bundle()));
+        }
+    }
+    load(
+        bucket,
+        &settings.keys.bundle_key(subject),
+        deserialise_vector,
+    )
+}
+
+/// Answer a similarity query (see [`query`]).
+#[cfg(not(test))]
+fn answer_query(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    settings: &settings::Settings,
+    schema: Option<&schema::Schema>,
+    request: &query::QueryRequest,
+) -> Result<query::QueryResponse, String> {
+    use crate::baseline::BundleMeta;
+    use crate::history::History;
+    use crate::query::{QueryMatch, QueryResponse};
+
+    let example = request.example_body();
+    let subjects = match &request.subject {
+        Some(subject) => vec![subject.clone()],
+        None => list_subjects(bucket, &settings.keys)?.into_iter().collect(),
+    };
+
+    let mut matches = Vec::new();
+    for subject in subjects {
+        let encoder = settings.encoder_for(schema, &subject)?;
+        let field_ids = load(
+            bucket,
+            &settings.keys.field_ids_key(&subject),
+            FieldIds::from_json,
+        )?
+        .unwrap_or_default();
+        let meta = load(
+            bucket,
+            &settings.keys.bundle_meta_key(&subject),
+            BundleMeta::from_json,
+        )?
+        .unwrap_or_default();
+        let example = encode_example(&example, &encoder, &field_ids, &meta)?;
+        let Some(query_vec) = &example.bundle else {
+            continue;
+        };
+        let breakdown = |bundle: &SparseVec| {
+            let mut fields = example.deviations(bundle, &meta.field_types);
+            fields.truncate(settings.alert_top_fields);
+            fields
+        };
+        if request.subject.is_some() {
+            let history = load(
+                bucket,
+                &settings.keys.history_key(&subject),
+                History::from_bytes,
+            )?
+            .unwrap_or_default();
+            matches.extend(
+                history
+                    .search(query_vec, None, request.k)
+                    .into_iter()
+                    .map(|hit| QueryMatch {
+                        subject: subject.clone(),
+                        message: Some(hit.message),
+                        timestamp: Some(hit.at_ms),
+                        score: hit.score,
+                        fields: history
+                            .message_bundle(hit.message)
+                            .as_ref()
+                            .map(breakdown)
+                            .unwrap_or_default(),
+                    }),
+            );
+        } else if let Some(bundle) = load(
+            bucket,
+            &settings.keys.bundle_key(&subject),
+            deserialise_vector,
+        )? {
+            matches.push(QueryMatch {
+                score: query_vec.cosine(&bundle),
+                fields: breakdown(&bundle),
+                subject,
+                message: None,
+                timestamp: None,
+            });
+        }
+    }
+    Ok(QueryResponse::top_k(matches, request.k))
+}
+
 #[cfg(not(test))]
 struct PatternMonitor;
 
@@ -89,9 +753,14 @@ impl crate::exports::wasmcloud::messaging::handler::Guest for PatternMonitor {
     fn handle_message(
         msg: crate::exports::wasmcloud::messaging::handler::BrokerMessage,
     ) -> Result<(), String> {
+        use crate::baseline::{BundleMeta, BundleVotes};
+        use crate::decay::DecayingBundle;
+        use crate::history::History;
+        use crate::prototype::Prototypes;
+        use crate::sequence_patterns::{SequenceAlert, SequenceStore};
         use crate::wasi::keyvalue::store;
         use crate::wasi::logging::logging::{log, Level};
-        use embeddenator_retrieval::search::{two_stage_search, SearchConfig};
+        use crate::window::{WindowBundle, WindowIndex};
 
         let subject = msg.subject.clone();
 
@@ -105,8 +774,44 @@ impl crate::exports::wasmcloud::messaging::handler::Guest for PatternMonitor {
             ),
         );
 
+        let settings = load_settings()?;
+        let bucket = store::open(&settings.bucket).map_err(kv_err)?;
+        // A schema stored in the bucket takes precedence over an inline one.
+        let stored_schema = load_stored_schema(&bucket, &settings)?;
+        let schema = stored_schema.as_ref().or(settings.schema.as_ref());
+
+        // ── Queries are answered, never folded into a baseline ────────────────
+        if schema::subject_matches(&settings.query_subject, &subject) {
+            let Some(reply_to) = msg.reply_to.as_deref() else {
+                log(
+                    Level::Warn,
+                    "pattern-monitor",
+                    &format!("query on '{subject}' has no reply subject; ignoring"),
+                );
+                return Ok(());
+            };
+            let response = query::QueryRequest::parse(&msg.body)
+                .and_then(|request| answer_query(&bucket, &settings, schema, &request))
+                .unwrap_or_else(|error| query::QueryResponse::Error { error });
+            if let query::QueryResponse::Error { error } = &response {
+                log(
+                    Level::Warn,
+                    "pattern-monitor",
+                    &format!("query on '{subject}' failed: {error}"),
+                );
+            }
+            return publish(reply_to, response.to_json()?);
+        }
+
         // ── 1. Encode fields ──────────────────────────────────────────────────
-        let encoded = match encode_json_fields(&msg.body) {
+        let encoder = settings.encoder_for(schema, &subject)?;
+        let field_ids_key = settings.keys.field_ids_key(&subject);
+        let mut field_ids = match bucket.get(&field_ids_key).map_err(kv_err)? {
+            Some(bytes) => FieldIds::from_json(&bytes)?,
+            None => FieldIds::default(),
+        };
+        let known_ids = field_ids.next_id;
+        let encoded = match encode_json_fields(&msg.body, &encoder, &mut field_ids) {
             Ok(e) if e.id_to_vec.is_empty() => {
                 log(
                     Level::Warn,
@@ -129,67 +834,902 @@ impl crate::exports::wasmcloud::messaging::handler::Guest for PatternMonitor {
         let EncodedFields {
             id_to_vec,
             id_to_field,
-            index,
+            id_to_type,
+            id_to_value,
         } = encoded;
 
+        if field_ids.next_id != known_ids {
+            bucket
+                .set(&field_ids_key, &field_ids.to_json()?)
+                .map_err(kv_err)?;
+            log(
+                Level::Debug,
+                "pattern-monitor",
+                &format!(
+                    "assigned {} new field id(s) on '{}'",
+                    field_ids.next_id - known_ids,
+                    subject
+                ),
+            );
+        }
+
         // ── 2. Persist semantic vectors ───────────────────────────────────────
-        let bucket = store::open(BUCKET_ID).map_err(kv_err)?;
+        let migrated = migrate_legacy_semantic_keys(&bucket, &settings.keys)?;
+        if migrated > 0 {
+            log(
+                Level::Info,
+                "pattern-monitor",
+                &format!("migrated {migrated} legacy semantic:v1 key(s) to semantic:v2"),
+            );
+        }
 
         for (id, vec) in &id_to_vec {
             let field_name = id_to_field.get(id).map(String::as_str).unwrap_or("unknown");
             let bytes = serialise_vector(vec)?;
-            let kv_key = format!("{PREFIX_SEMANTIC}:{field_name}");
+            let kv_key = settings.keys.semantic_key(&subject, field_name);
             bucket.set(&kv_key, &bytes).map_err(kv_err)?;
             log(
                 Level::Debug,
                 "pattern-monitor",
                 &format!(
-                    "stored semantic vector for field '{}' ({} bytes)",
+                    "stored semantic vector for field '{}' on '{}' ({} bytes)",
                     field_name,
+                    subject,
                     bytes.len()
                 ),
             );
         }
 
-        // ── 3. Build and persist master bundle ────────────────────────────────
-        if let Some(master) = build_master_bundle(&id_to_vec) {
+        // Remember each field's value for recall from the bundle.
+        if settings.codebook_limit > 0 {
+            let codebook_key = settings.keys.codebook_key(&subject);
+            let mut codebook =
+                load(&bucket, &codebook_key, codebook::Codebook::from_bytes)?.unwrap_or_default();
+            for (id, bound) in &id_to_vec {
+                if let (Some(field), Some(value)) = (id_to_field.get(id), id_to_value.get(id)) {
+                    codebook.observe(field, value, bound, settings.codebook_limit);
+                }
+            }
+            bucket
+                .set(&codebook_key, &codebook.to_bytes()?)
+                .map_err(kv_err)?;
+        }
+
+        // ── 3. Accumulate and persist master bundle ───────────────────────────
+        let bundle_key = settings.keys.bundle_key(&subject);
+        let meta_key = settings.keys.bundle_meta_key(&subject);
+
+        let stored_bundle = bucket
+            .get(&bundle_key)
+            .map_err(kv_err)?
+            .map(|bytes| deserialise_vector(&bytes))
+            .transpose()?;
+        let stored_meta = match bucket.get(&meta_key).map_err(kv_err)? {
+            Some(bytes) => BundleMeta::from_json(&bytes)?,
+            // Bundles written before metadata existed count as one message.
+            None if stored_bundle.is_some() => BundleMeta {
+                message_count: 1,
+                ..Default::default()
+            },
+            None => BundleMeta::default(),
+        };
+        let votes_key = settings.keys.bundle_votes_key(&subject);
+        // Bundles written before votes were kept carry on as if every
+        // earlier message had equalled the bundle.
+        let stored_votes = match load(&bucket, &votes_key, BundleVotes::from_bytes)? {
+            Some(votes) => Some(votes),
+            None => stored_bundle
+                .as_ref()
+                .map(|bundle| BundleVotes::seeded(bundle, stored_meta.message_count)),
+        };
+        let decay_key = settings.keys.decay_key(&subject);
+        let decaying = if settings.decay_half_life > 0 {
+            load(&bucket, &decay_key, DecayingBundle::from_bytes)?
+        } else {
+            None
+        };
+        // With a half-life, messages are scored against the decaying
+        // baseline; until it has seen a message, the master bundle stands in.
+        let decayed_bundle = decaying
+            .as_ref()
+            .filter(|d| d.message_count > 0)
+            .map(DecayingBundle::bundle);
+        let scoring_baseline = decayed_bundle.as_ref().or(stored_bundle.as_ref());
+
+        let field_weights = weights::resolve_weights(
+            &id_to_field,
+            &encoder.field_weights,
+            encoder
+                .learn_weights
+                .then_some(&stored_meta.field_variability),
+        );
+
+        let message_bundle = build_master_bundle(&id_to_vec, &field_weights);
+        if let Some(message_bundle) = &message_bundle {
+            // Score against the baseline before the message is folded into it.
+            let score = scoring_baseline
+                .map(|baseline| anomaly::score_against_baseline(message_bundle, baseline));
+            match score {
+                Some(score) => log(
+                    Level::Info,
+                    "pattern-monitor",
+                    &format!(
+                        "novelty score for subject '{}': {:.4} (similarity {:.4} vs {} message(s))",
+                        subject, score.novelty, score.similarity, stored_meta.message_count,
+                    ),
+                ),
+                None => log(
+                    Level::Info,
+                    "pattern-monitor",
+                    &format!("no baseline for subject '{subject}' yet; not scoring"),
+                ),
+            }
+
+            let deviations = scoring_baseline
+                .map(|baseline| {
+                    anomaly::field_deviations(
+                        &id_to_vec,
+                        &id_to_field,
+                        &id_to_type,
+                        &field_weights,
+                        baseline,
+                        &stored_meta.field_types,
+                    )
+                })
+                .unwrap_or_default();
+
+            if let Some(score) = score {
+                log(
+                    Level::Info,
+                    "pattern-monitor",
+                    &format!(
+                        "most deviating fields on subject '{}': {}",
+                        subject,
+                        anomaly::describe_deviations(&deviations, settings.alert_top_fields),
+                    ),
+                );
+                for change in deviations.iter().filter(|d| d.type_changed()) {
+                    log(
+                        Level::Warn,
+                        "pattern-monitor",
+                        &format!(
+                            "type change on subject '{}' field '{}': {:?} -> {:?}",
+                            subject, change.field, change.baseline_type, change.json_type,
+                        ),
+                    );
+                }
+                if let Some(alert) = alert::AnomalyAlert::from_score(
+                    &subject,
+                    score,
+                    settings.novelty_threshold,
+                    deviations.clone(),
+                    settings.alert_top_fields,
+                    now_millis(),
+                ) {
+                    // A failed publish must not lose the baseline update below.
+                    let published = alert
+                        .to_json()
+                        .and_then(|body| publish(&settings.alert_subject, body));
+                    match published {
+                        Ok(()) => log(
+                            Level::Warn,
+                            "pattern-monitor",
+                            &format!(
+                                "anomaly on subject '{}': novelty {:.4} >= {:.4}; alert published to '{}'",
+                                subject,
+                                score.novelty,
+                                settings.novelty_threshold,
+                                settings.alert_subject,
+                            ),
+                        ),
+                        Err(err) => log(
+                            Level::Error,
+                            "pattern-monitor",
+                            &format!("failed to publish anomaly alert for '{subject}': {err}"),
+                        ),
+                    }
+                }
+            }
+
+            let (votes, mut meta) = baseline::update_baseline(
+                stored_votes.as_ref().map(|v| (v, &stored_meta)),
+                message_bundle,
+                score,
+            );
+            let master = votes.bundle();
+            meta.record_field_types(
+                id_to_field
+                    .iter()
+                    .filter_map(|(id, field)| Some((field, *id_to_type.get(id)?))),
+            );
+            meta.record_field_deviations(&deviations);
             let bundle_bytes = serialise_vector(&master)?;
-            let bundle_key = format!("{PREFIX_BUNDLE}:{subject}");
             bucket.set(&bundle_key, &bundle_bytes).map_err(kv_err)?;
+            bucket.set(&votes_key, &votes.to_bytes()?).map_err(kv_err)?;
+            if stored_bundle.is_none() {
+                register_subject(&bucket, &settings.keys, &subject)?;
+            }
+            bucket.set(&meta_key, &meta.to_json()?).map_err(kv_err)?;
             log(
                 Level::Info,
                 "pattern-monitor",
                 &format!(
-                    "stored master bundle for subject '{}' ({} fields, {} bytes)",
+                    "updated master bundle for subject '{}' ({} fields, {} messages, {} bytes)",
                     subject,
                     id_to_vec.len(),
+                    meta.message_count,
                     bundle_bytes.len(),
                 ),
             );
+
+            if settings.decay_half_life > 0 {
+                let mut decaying = decaying.unwrap_or_default();
+                decaying.fold(message_bundle, settings.decay_half_life);
+                bucket
+                    .set(&decay_key, &decaying.to_bytes()?)
+                    .map_err(kv_err)?;
+            }
+        }
+
+        // ── 4. Extend the current time window ─────────────────────────────────
+        if let Some(message_bundle) = message_bundle
+            .as_ref()
+            .filter(|_| settings.window_retention > 0)
+        {
+            let now = now_millis();
+            let size = settings.window_size;
+            let start = size.start_of(now);
+            let window_key = settings.keys.window_key(&subject, size, start);
+            let window = match load(&bucket, &window_key, WindowBundle::from_bytes)? {
+                Some(mut window) => {
+                    window.fold(message_bundle);
+                    window
+                }
+                None => {
+                    // A new window opened: check the two latest complete
+                    // windows for drift, then drop those out of retention.
+                    let indexed = index_legacy_windows(&bucket, &settings.keys)?;
+                    if indexed > 0 {
+                        log(
+                            Level::Info,
+                            "pattern-monitor",
+                            &format!(
+                                "indexed {indexed} window bundle(s) stored before window indexes"
+                            ),
+                        );
+                    }
+                    let index_key = settings.keys.window_index_key(&subject);
+                    let mut index =
+                        load(&bucket, &index_key, WindowIndex::from_json)?.unwrap_or_default();
+                    if let Some((previous, latest)) = index.latest_pair(size, start) {
+                        let previous = settings.keys.window_key(&subject, size, previous);
+                        let latest = settings.keys.window_key(&subject, size, latest);
+                        if let Err(err) =
+                            report_drift(&bucket, &settings, &subject, &previous, &latest)
+                        {
+                            log(
+                                Level::Error,
+                                "pattern-monitor",
+                                &format!("drift check failed for '{subject}': {err}"),
+                            );
+                        }
+                    }
+                    let expired = index.expire(now, settings.window_retention);
+                    for (expired_size, expired_start) in &expired {
+                        bucket
+                            .delete(&settings.keys.window_key(
+                                &subject,
+                                *expired_size,
+                                *expired_start,
+                            ))
+                            .map_err(kv_err)?;
+                    }
+                    index.insert(size, start);
+                    bucket.set(&index_key, &index.to_json()?).map_err(kv_err)?;
+                    log(
+                        Level::Info,
+                        "pattern-monitor",
+                        &format!(
+                            "opened {} window {} for subject '{}' ({} expired window(s) deleted)",
+                            size.as_str(),
+                            start,
+                            subject,
+                            expired.len(),
+                        ),
+                    );
+                    WindowBundle::new(size, start, message_bundle)
+                }
+            };
+            bucket
+                .set(&window_key, &window.to_bytes()?)
+                .map_err(kv_err)?;
         }
 
-        // ── 4. Demonstrate retrieval ──────────────────────────────────────────
-        if id_to_vec.len() > 1 {
-            if let Some(query_vec) = id_to_vec.get(&0) {
-                let query_field = id_to_field.get(&0).map(String::as_str).unwrap_or("field_0");
-                let search_cfg = SearchConfig::default();
-                let results = two_stage_search(query_vec, &index, &id_to_vec, &search_cfg, 5);
+        // ── 5. Assign the message to a pattern prototype ──────────────────────
+        if let Some(message_bundle) = message_bundle
+            .as_ref()
+            .filter(|_| settings.prototype_limit > 0)
+        {
+            let prototypes_key = settings.keys.prototypes_key(&subject);
+            let mut prototypes =
+                load(&bucket, &prototypes_key, Prototypes::from_bytes)?.unwrap_or_default();
+            let assignment = prototypes.assign(
+                message_bundle,
+                now_millis(),
+                settings.prototype_limit,
+                settings.prototype_spawn_threshold,
+                settings.prototype_merge_threshold,
+            );
+            bucket
+                .set(&prototypes_key, &prototypes.to_bytes()?)
+                .map_err(kv_err)?;
+            let placed = if assignment.spawned {
+                format!(
+                    "spawned prototype #{} (closest was {:.4})",
+                    assignment.prototype, assignment.similarity
+                )
+            } else {
+                format!(
+                    "matched prototype #{} (similarity {:.4})",
+                    assignment.prototype, assignment.similarity
+                )
+            };
+            log(
+                Level::Info,
+                "pattern-monitor",
+                &format!("message on '{subject}' {placed}"),
+            );
+            if !assignment.merged.is_empty() {
+                log(
+                    Level::Info,
+                    "pattern-monitor",
+                    &format!(
+                        "merged prototype(s) {:?} on '{}'; {} prototype(s) remain",
+                        assignment.merged,
+                        subject,
+                        prototypes.prototypes.len(),
+                    ),
+                );
+            }
+        }
+
+        // ── 6. Match the latest messages against known sequences ─────────────
+        if let Some(message_bundle) = message_bundle
+            .as_ref()
+            .filter(|_| settings.sequence_length > 0)
+        {
+            let sequences_key = settings.keys.sequences_key(&subject);
+            let mut sequences =
+                load(&bucket, &sequences_key, SequenceStore::from_bytes)?.unwrap_or_default();
+            let observation = sequences.observe(
+                message_bundle,
+                settings.sequence_length,
+                settings.sequence_limit,
+                settings.sequence_threshold,
+                settings.sequence_warmup,
+            );
+            bucket
+                .set(&sequences_key, &sequences.to_bytes()?)
+                .map_err(kv_err)?;
+            if let Some(similarity) = observation.similarity {
+                log(
+                    Level::Debug,
+                    "pattern-monitor",
+                    &format!(
+                        "last {} messages on '{}' match a known sequence by {:.4}",
+                        settings.sequence_length, subject, similarity,
+                    ),
+                );
+            }
+            for finding in &observation.findings {
+                let published = SequenceAlert::new(&subject, finding, now_millis())
+                    .to_json()
+                    .and_then(|body| publish(&settings.alert_subject, body));
+                match published {
+                    Ok(()) => log(
+                        Level::Warn,
+                        "pattern-monitor",
+                        &format!(
+                            "{:?} sequence of {} message(s) on '{}'{}; alert published to '{}'",
+                            finding.kind,
+                            finding.length,
+                            subject,
+                            finding
+                                .pattern
+                                .as_ref()
+                                .map(|name| format!(" matching '{name}'"))
+                                .unwrap_or_default(),
+                            settings.alert_subject,
+                        ),
+                    ),
+                    Err(err) => log(
+                        Level::Error,
+                        "pattern-monitor",
+                        &format!("failed to publish sequence alert for '{subject}': {err}"),
+                    ),
+                }
+            }
+        }
+
+        // ── 7. Search and extend the subject's history ────────────────────────
+        if let Some(message_bundle) = message_bundle
+            .as_ref()
+            .filter(|_| settings.history_limit > 0)
+        {
+            let history_key = settings.keys.history_key(&subject);
+            let mut history = match bucket.get(&history_key).map_err(kv_err)? {
+                Some(bytes) => History::from_bytes(&bytes)?,
+                None => History::default(),
+            };
+            let similar = history.search(message_bundle, None, 3);
+            if !similar.is_empty() {
+                let listed: Vec<String> = similar
+                    .iter()
+                    .map(|hit| format!("#{} ({:.4})", hit.message, hit.score))
+                    .collect();
                 log(
                     Level::Debug,
                     "pattern-monitor",
                     &format!(
-                        "retrieval query for field '{}' returned {} result(s)",
-                        query_field,
-                        results.len(),
+                        "closest past messages on '{}': {}",
+                        subject,
+                        listed.join(", ")
                     ),
                 );
             }
+            history.record(
+                message_bundle,
+                &id_to_vec,
+                now_millis(),
+                settings.history_limit,
+            );
+            let history_bytes = history.to_bytes()?;
+            bucket.set(&history_key, &history_bytes).map_err(kv_err)?;
+            log(
+                Level::Debug,
+                "pattern-monitor",
+                &format!(
+                    "history for '{}' holds {} message(s) ({} bytes)",
+                    subject,
+                    history.message_count(),
+                    history_bytes.len(),
+                ),
+            );
         }
 
         Ok(())
     }
 }
 
+/// Whether this instance has seen [`keys::KEY_WINDOW_SCHEMA`].
+#[cfg(not(test))]
+static LEGACY_WINDOWS_INDEXED: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
+/// Add window bundles stored before window indexes existed to their
+/// subjects' indexes. Scans the bucket once per bucket, guarded by
+/// [`keys::KEY_WINDOW_SCHEMA`], and checks the guard once per instance.
+#[cfg(not(test))]
+fn index_legacy_windows(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    key_schema: &keys::KeySchema,
+) -> Result<usize, String> {
+    use std::sync::atomic::Ordering;
+
+    if LEGACY_WINDOWS_INDEXED.load(Ordering::Relaxed) {
+        return Ok(0);
+    }
+    if bucket.exists(keys::KEY_WINDOW_SCHEMA).map_err(kv_err)? {
+        LEGACY_WINDOWS_INDEXED.store(true, Ordering::Relaxed);
+        return Ok(0);
+    }
+
+    let mut by_subject: BTreeMap<String, Vec<(window::WindowSize, u64)>> = BTreeMap::new();
+    for key in list_all_keys(bucket)? {
+        if let Some((subject, size, start)) = key_schema.window_of(&key) {
+            by_subject
+                .entry(subject.to_string())
+                .or_default()
+                .push((size, start));
+        }
+    }
+    let mut indexed = 0;
+    for (subject, windows) in by_subject {
+        let index_key = key_schema.window_index_key(&subject);
+        let mut index =
+            load(bucket, &index_key, window::WindowIndex::from_json)?.unwrap_or_default();
+        for (size, start) in windows {
+            index.insert(size, start);
+            indexed += 1;
+        }
+        bucket.set(&index_key, &index.to_json()?).map_err(kv_err)?;
+    }
+
+    bucket
+        .set(keys::KEY_WINDOW_SCHEMA, key_schema.window.as_bytes())
+        .map_err(kv_err)?;
+    LEGACY_WINDOWS_INDEXED.store(true, Ordering::Relaxed);
+    Ok(indexed)
+}
+
+/// Compare two stored windows of `subject` and, if they drifted apart,
+/// persist the drift event and publish it on the drift subject.
+#[cfg(not(test))]
+fn report_drift(
+    bucket: &crate::wasi::keyvalue::store::Bucket,
+    settings: &settings::Settings,
+    subject: &str,
+    previous_key: &str,
+    latest_key: &str,
+) -> Result<(), String> {
+    use crate::wasi::logging::logging::{log, Level};
+    use crate::window::WindowBundle;
+
+    let (Some(previous), Some(latest)) = (
+        load(bucket, previous_key, WindowBundle::from_bytes)?,
+        load(bucket, latest_key, WindowBundle::from_bytes)?,
+    ) else {
+        return Ok(());
+    };
+    let codebook = load(
+        bucket,
+        &settings.keys.codebook_key(subject),
+        codebook::Codebook::from_bytes,
+    )?
+    .unwrap_or_default();
+    let Some(event) = drift::DriftEvent::detect(
+        subject,
+        &previous,
+        &latest,
+        &codebook,
+        settings.drift_threshold,
+        settings.alert_top_fields,
+        now_millis(),
+    ) else {
+        return Ok(());
+    };
+    let body = event.to_json()?;
+    bucket
+        .set(&settings.keys.drift_key(subject, latest.start_ms), &body)
+        .map_err(kv_err)?;
+    publish(&settings.drift_subject, body)?;
+    log(
+        Level::Warn,
+        "pattern-monitor",
+        &format!(
+            "drift on subject '{}': magnitude {:.4} >= {:.4} between windows {} and {}; event published to '{}'",
+            subject,
+            event.magnitude,
+            settings.drift_threshold,
+            previous.start_ms,
+            latest.start_ms,
+            settings.drift_subject,
+        ),
+    );
+    Ok(())
+}
+
+/// Read the body of an incoming HTTP request, up to
+/// [`routes::MAX_BODY_BYTES`].
+#[cfg(not(test))]
+fn read_http_body(
+    request: &crate::wasi::http::types::IncomingRequest,
+) -> Result<Vec<u8>, routes::RouteError> {
+    use crate::wasi::http::types::IncomingBody;
+    use crate::wasi::io::streams::StreamError;
+
+    let failed = |message: String| routes::RouteError {
+        status: 400,
+        message,
+    };
+    let body = request
+        .consume()
+        .map_err(|()| failed("request body unavailable".to_string()))?;
+    let stream = body
+        .stream()
+        .map_err(|()| failed("request body unavailable".to_string()))?;
+    let mut bytes = Vec::new();
+    loop {
+        match stream.blocking_read(64 * 1024) {
+            Ok(chunk) => bytes.extend(chunk),
+            Err(StreamError::Closed) => break,
+            Err(StreamError::LastOperationFailed(e)) => {
+                return Err(failed(format!(
+                    "request body read error: {}",
+                    e.to_debug_string()
+                )))
+            }
+        }
+        if bytes.len() > routes::MAX_BODY_BYTES {
+            return Err(routes::RouteError {
+                status: 413,
+                message: format!("request body exceeds {} bytes", routes::MAX_BODY_BYTES),
+            });
+        }
+    }
+    drop(stream);
+    IncomingBody::finish(body);
+    Ok(bytes)
+}
+
+/// Answer an HTTP request (see [`routes`]) with a JSON body.
+#[cfg(not(test))]
+fn serve_http(
+    method: &str,
+    path: &str,
+    request: &crate::wasi::http::types::IncomingRequest,
+) -> Result<Vec<u8>, routes::RouteError> {
+    use crate::baseline::BundleMeta;
+    use crate::query::{QueryRequest, ScoreRequest};
+    use crate::routes::{Route, RouteError};
+    use crate::sequence_patterns::{SequenceRegistration, SequenceStore};
+    use crate::wasi::keyvalue::store;
+    use crate::window::WindowBundle;
+
+    let route = Route::parse(method, path)?;
+    let internal = |message: String| RouteError {
+        status: 500,
+        message,
+    };
+    let bad_request = |message: String| RouteError {
+        status: 400,
+        message,
+    };
+    let not_found = |message: String| RouteError {
+        status: 404,
+        message,
+    };
+
+    let settings = load_settings().map_err(internal)?;
+    let bucket = store::open(&settings.bucket)
+        .map_err(kv_err)
+        .map_err(internal)?;
+    let stored_schema = load_stored_schema(&bucket, &settings).map_err(internal)?;
+    let schema = stored_schema.as_ref().or(settings.schema.as_ref());
+
+    let body = match route {
+        Route::Subjects => serde_json::json!({
+            "subjects": list_subjects(&bucket, &settings.keys).map_err(internal)?,
+        })
+        .to_string()
+        .into_bytes(),
+        Route::Bundle(subject) => {
+            let meta = load(
+                &bucket,
+                &settings.keys.bundle_meta_key(&subject),
+                BundleMeta::from_json,
+            )
+            .map_err(internal)?;
+            let has_bundle = bucket
+                .exists(&settings.keys.bundle_key(&subject))
+                .map_err(kv_err)
+                .map_err(internal)?;
+            let meta = match meta {
+                Some(meta) => meta,
+                // Bundles written before metadata existed count as one message.
+                None if has_bundle => BundleMeta {
+                    message_count: 1,
+                    ..Default::default()
+                },
+                None => return Err(not_found(format!("no bundle for subject '{subject}'"))),
+            };
+            serde_json::json!({ "subject": subject, "meta": meta })
+                .to_string()
+                .into_bytes()
+        }
+        Route::Values(subject, field) => {
+            let bundle = load(
+                &bucket,
+                &settings.keys.bundle_key(&subject),
+                deserialise_vector,
+            )
+            .map_err(internal)?
+            .ok_or_else(|| not_found(format!("no bundle for subject '{subject}'")))?;
+            let codebook = load(
+                &bucket,
+                &settings.keys.codebook_key(&subject),
+                codebook::Codebook::from_bytes,
+            )
+            .map_err(internal)?
+            .unwrap_or_default();
+            let values = codebook.recall(&bundle, &field);
+            if values.is_empty() {
+                return Err(not_found(format!(
+                    "no known values for field '{field}' on subject '{subject}'"
+                )));
+            }
+            serde_json::json!({ "subject": subject, "field": field, "values": values })
+                .to_string()
+                .into_bytes()
+        }
+        Route::Windows(subject) => {
+            index_legacy_windows(&bucket, &settings.keys).map_err(internal)?;
+            let index = load(
+                &bucket,
+                &settings.keys.window_index_key(&subject),
+                window::WindowIndex::from_json,
+            )
+            .map_err(internal)?
+            .unwrap_or_default();
+            let mut windows = Vec::new();
+            for (size, start) in index.windows {
+                let key = settings.keys.window_key(&subject, size, start);
+                if let Some(window) =
+                    load(&bucket, &key, WindowBundle::from_bytes).map_err(internal)?
+                {
+                    windows.push(window);
+                }
+            }
+            if windows.is_empty() {
+                return Err(not_found(format!("no windows for subject '{subject}'")));
+            }
+            serde_json::json!({ "subject": subject, "windows": window::compare_windows(windows) })
+                .to_string()
+                .into_bytes()
+        }
+        Route::Prototypes(subject) => {
+            let prototypes = load(
+                &bucket,
+                &settings.keys.prototypes_key(&subject),
+                prototype::Prototypes::from_bytes,
+            )
+            .map_err(internal)?
+            .ok_or_else(|| not_found(format!("no prototypes for subject '{subject}'")))?;
+            serde_json::json!({ "subject": subject, "prototypes": prototypes.summaries() })
+                .to_string()
+                .into_bytes()
+        }
+        Route::Sequences(subject) => {
+            let sequences = load(
+                &bucket,
+                &settings.keys.sequences_key(&subject),
+                SequenceStore::from_bytes,
+            )
+            .map_err(internal)?
+            .ok_or_else(|| not_found(format!("no sequences for subject '{subject}'")))?;
+            serde_json::json!({ "subject": subject, "sequences": sequences.summaries() })
+                .to_string()
+                .into_bytes()
+        }
+        Route::RegisterSequence(subject) => {
+            let registration =
+                SequenceRegistration::parse(&read_http_body(request)?).map_err(bad_request)?;
+            let encoder = settings.encoder_for(schema, &subject).map_err(internal)?;
+            let field_ids = load(
+                &bucket,
+                &settings.keys.field_ids_key(&subject),
+                FieldIds::from_json,
+            )
+            .map_err(internal)?
+            .unwrap_or_default();
+            let meta = load(
+                &bucket,
+                &settings.keys.bundle_meta_key(&subject),
+                BundleMeta::from_json,
+            )
+            .map_err(internal)?
+            .unwrap_or_default();
+            let mut messages = Vec::new();
+            for (i, body) in registration.message_bodies().iter().enumerate() {
+                let example =
+                    encode_example(body, &encoder, &field_ids, &meta).map_err(bad_request)?;
+                messages.push(
+                    example.bundle.ok_or_else(|| {
+                        bad_request(format!("message {i} has no weighted fields"))
+                    })?,
+                );
+            }
+            let key = settings.keys.sequences_key(&subject);
+            let mut sequences = load(&bucket, &key, SequenceStore::from_bytes)
+                .map_err(internal)?
+                .unwrap_or_default();
+            sequences
+                .register(
+                    &registration.name,
+                    &messages,
+                    registration.suspicious,
+                    settings.sequence_length,
+                )
+                .map_err(bad_request)?;
+            bucket
+                .set(&key, &sequences.to_bytes().map_err(internal)?)
+                .map_err(kv_err)
+                .map_err(internal)?;
+            serde_json::json!({
+                "subject": subject,
+                "name": registration.name,
+                "length": messages.len(),
+                "suspicious": registration.suspicious,
+            })
+            .to_string()
+            .into_bytes()
+        }
+        Route::Similar => {
+            let request = QueryRequest::parse(&read_http_body(request)?).map_err(bad_request)?;
+            answer_query(&bucket, &settings, schema, &request)
+                .and_then(|response| response.to_json())
+                .map_err(internal)?
+        }
+        Route::Score => {
+            let request = ScoreRequest::parse(&read_http_body(request)?).map_err(bad_request)?;
+            let response = score_request(&bucket, &settings, schema, &request)
+                .map_err(internal)?
+                .ok_or_else(|| {
+                    not_found(format!(
+                        "no baseline for subject '{}' to score against",
+                        request.subject
+                    ))
+                })?;
+            serde_json::to_vec(&response)
+                .map_err(|e| internal(format!("score encode error: {e}")))?
+        }
+    };
+    Ok(body)
+}
+
+/// Send `body` as a JSON response with `status`.
+#[cfg(not(test))]
+fn respond_http(out: crate::wasi::http::types::ResponseOutparam, status: u16, body: &[u8]) {
+    use crate::wasi::http::types::{Fields, OutgoingBody, OutgoingResponse, ResponseOutparam};
+
+    let headers = Fields::from_list(&[("content-type".to_string(), b"application/json".to_vec())])
+        .unwrap_or_else(|_| Fields::new());
+    let response = OutgoingResponse::new(headers);
+    // Every status used by the routes is valid.
+    let _ = response.set_status_code(status);
+    let Ok(outgoing) = response.body() else {
+        return;
+    };
+    ResponseOutparam::set(out, Ok(response));
+    if let Ok(stream) = outgoing.write() {
+        // blocking-write-and-flush accepts at most 4096 bytes per call.
+        for chunk in body.chunks(4096) {
+            if stream.blocking_write_and_flush(chunk).is_err() {
+                break;
+            }
+        }
+    }
+    let _ = OutgoingBody::finish(outgoing, None);
+}
+
+#[cfg(not(test))]
+impl crate::exports::wasi::http::incoming_handler::Guest for PatternMonitor {
+    fn handle(
+        request: crate::wasi::http::types::IncomingRequest,
+        response_out: crate::wasi::http::types::ResponseOutparam,
+    ) {
+        use crate::wasi::http::types::Method;
+        use crate::wasi::logging::logging::{log, Level};
+
+        let method = match request.method() {
+            Method::Get => "GET".to_string(),
+            Method::Head => "HEAD".to_string(),
+            Method::Post => "POST".to_string(),
+            Method::Put => "PUT".to_string(),
+            Method::Delete => "DELETE".to_string(),
+            Method::Connect => "CONNECT".to_string(),
+            Method::Options => "OPTIONS".to_string(),
+            Method::Trace => "TRACE".to_string(),
+            Method::Patch => "PATCH".to_string(),
+            Method::Other(other) => other,
+        };
+        let path = request.path_with_query().unwrap_or_default();
+        let (status, body) = match serve_http(&method, &path, &request) {
+            Ok(body) => (200, body),
+            Err(err) => {
+                log(
+                    Level::Warn,
+                    "pattern-monitor",
+                    &format!("{method} {path} failed ({}): {}", err.status, err.message),
+                );
+                let body = serde_json::json!({ "error": err.message });
+                (err.status, body.to_string().into_bytes())
+            }
+        };
+        respond_http(response_out, status, &body);
+    }
+}
+
 #[cfg(not(test))]
 export!(PatternMonitor);
 
@@ -198,21 +1738,400 @@ export!(PatternMonitor);
 #[cfg(test)]
 mod tests {
     use super::*;
-    use embeddenator_io::from_bincode;
+
+    #[test]
+    fn test_field_ids_are_stable_across_messages() {
+        let mut field_ids = FieldIds::default();
+        let encoder = EncoderConfig::default();
+        let first = encode_json_fields(
+            br#"{"status":"ok","event":"login"}"#,
+            &encoder,
+            &mut field_ids,
+        )
+        .unwrap();
+        let second = encode_json_fields(
+            br#"{"region":"eu","event":"logout","status":"failed"}"#,
+            &encoder,
+            &mut field_ids,
+        )
+        .unwrap();
+        let id_of = |enc: &EncodedFields, name: &str| {
+            *enc.id_to_field.iter().find(|(_, f)| *f == name).unwrap().0
+        };
+        assert_eq!(id_of(&first, "event"), 0);
+        assert_eq!(id_of(&first, "status"), 1);
+        assert_eq!(id_of(&second, "event"), 0);
+        assert_eq!(id_of(&second, "status"), 1);
+        assert_eq!(id_of(&second, "region"), 2);
+        assert_eq!(field_ids.next_id, 3);
+    }
+
+    #[test]
+    fn test_encode_example_matches_stored_message_without_new_ids() {
+        let encoder = EncoderConfig::default();
+        let mut field_ids = FieldIds::default();
+        let body = br#"{"status":"failed","region":"eu"}"#;
+        let stored = encode_json_fields(body, &encoder, &mut field_ids).unwrap();
+        let stored = build_master_bundle(&stored.id_to_vec, &HashMap::new()).unwrap();
+        let meta = baseline::BundleMeta::default();
+
+        let same = encode_example(body, &encoder, &field_ids, &meta)
+            .unwrap()
+            .bundle
+            .unwrap();
+        assert!((same.cosine(&stored) - 1.0).abs() < 1e-9);
+        let other = encode_example(
+            br#"{"status":"ok","host":"web-1"}"#,
+            &encoder,
+            &field_ids,
+            &meta,
+        )
+        .unwrap();
+        assert_eq!(other.fields.id_to_vec.len(), 2);
+        let other = other.bundle.unwrap();
+        assert!(other.cosine(&stored) < 0.5);
+        assert_eq!(field_ids.next_id, 2, "queries must not record field ids");
+    }
+
+    #[test]
+    fn test_score_example_reports_deviating_fields() {
+        let encoder = EncoderConfig::default();
+        let mut field_ids = FieldIds::default();
+        let usual = br#"{"status":"ok","region":"eu"}"#;
+        let stored = encode_json_fields(usual, &encoder, &mut field_ids).unwrap();
+        let baseline = build_master_bundle(&stored.id_to_vec, &HashMap::new()).unwrap();
+        let meta = baseline::BundleMeta::default();
+
+        let same = score_example("s", usual, &encoder, &field_ids, &meta, &baseline)
+            .unwrap()
+            .unwrap();
+        assert!(same.score.novelty < 1e-9);
+        let changed = score_example(
+            "s",
+            br#"{"status":"failed","region":"eu"}"#,
+            &encoder,
+            &field_ids,
+            &meta,
+            &baseline,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(changed.score.novelty > same.score.novelty);
+        assert_eq!(changed.fields[0].field, "status");
+        assert!(changed.fields[0].contribution > 0.5);
+    }
 
     #[test]
     fn test_encode_fields_parses_json_object() {
         let body = br#"{"event":"quake","magnitude":"6.2"}"#;
-        let result = encode_json_fields(body);
+        let result = encode_json_fields(body, &EncoderConfig::default(), &mut FieldIds::default());
         assert!(result.is_ok(), "expected Ok, got: {:?}", result.err());
         let encoded = result.unwrap();
         assert_eq!(encoded.id_to_vec.len(), 2, "expected 2 field vectors");
         assert_eq!(encoded.id_to_field.len(), 2, "expected 2 field names");
     }
 
+    #[test]
+    fn test_encode_fields_bound_vectors_keep_value_support() {
+        let encoded = encode_json_fields(
+            br#"{"event":"quake","magnitude":"6.2"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        for vec in encoded.id_to_vec.values() {
+            assert!(
+                !vec.pos.is_empty() || !vec.neg.is_empty(),
+                "bound field vectors must not collapse to zero"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_fields_flattens_nested_leaves() {
+        let encoded = encode_json_fields(
+            br#"{"geo":{"lat":1,"lon":2},"items":[{"sku":"a"}]}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let mut fields: Vec<&str> = encoded.id_to_field.values().map(String::as_str).collect();
+        fields.sort();
+        assert_eq!(fields, ["geo.lat", "geo.lon", "items[0].sku"]);
+    }
+
+    #[test]
+    fn test_nested_change_only_moves_its_leaf() {
+        let before = encode_json_fields(
+            br#"{"geo":{"lat":1,"lon":2}}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let after = encode_json_fields(
+            br#"{"geo":{"lat":9,"lon":2}}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let field_vec = |enc: &EncodedFields, name: &str| {
+            let id = enc.id_to_field.iter().find(|(_, f)| *f == name).unwrap().0;
+            enc.id_to_vec[id].clone()
+        };
+        let lon_sim = field_vec(&before, "geo.lon").cosine(&field_vec(&after, "geo.lon"));
+        assert!(
+            (lon_sim - 1.0).abs() < 1e-9,
+            "unchanged leaf must be identical"
+        );
+        let bundle_sim = build_master_bundle(&before.id_to_vec, &HashMap::new())
+            .unwrap()
+            .cosine(&build_master_bundle(&after.id_to_vec, &HashMap::new()).unwrap());
+        assert!(
+            bundle_sim > 0.3,
+            "bundles should stay similar, got {bundle_sim}"
+        );
+    }
+
+    #[test]
+    fn test_encode_fields_respects_max_depth() {
+        let body = br#"{"geo":{"lat":1,"lon":2}}"#;
+        let encoder = EncoderConfig {
+            max_depth: 1,
+            ..Default::default()
+        };
+        let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+        assert_eq!(encoded.id_to_field.len(), 1);
+        assert_eq!(encoded.id_to_field.values().next().unwrap(), "geo");
+    }
+
+    #[test]
+    fn test_hierarchical_mode_encodes_one_field_per_top_level_key() {
+        let body = br#"{"geo":{"lat":1,"lon":2},"event":"quake"}"#;
+        let encoder = EncoderConfig {
+            structure: StructureMode::parse("hierarchical").unwrap(),
+            ..Default::default()
+        };
+        let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+        let mut fields: Vec<&str> = encoded.id_to_field.values().map(String::as_str).collect();
+        fields.sort();
+        assert_eq!(fields, ["event", "geo"]);
+        assert_eq!(StructureMode::parse("nested"), None);
+    }
+
+    #[test]
+    fn test_sequence_mode_keeps_array_as_one_field() {
+        let encoder = EncoderConfig {
+            arrays: ArrayMode::parse("sequence").unwrap(),
+            ..Default::default()
+        };
+        let encode = |body: &[u8]| {
+            let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+            assert_eq!(encoded.id_to_field.values().next().unwrap(), "readings");
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        let base = encode(br#"{"readings":["low","mid","high"]}"#);
+        let extended = encode(br#"{"readings":["low","mid","high","peak"]}"#);
+        let reversed = encode(br#"{"readings":["high","mid","low"]}"#);
+        assert!(base.cosine(&extended) > base.cosine(&reversed));
+    }
+
+    #[test]
+    fn test_set_mode_ignores_array_order() {
+        let encoder = EncoderConfig {
+            arrays: ArrayMode::parse("set").unwrap(),
+            ..Default::default()
+        };
+        let a = encode_json_fields(
+            br#"{"tags":["a","b","c"]}"#,
+            &encoder,
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let b = encode_json_fields(
+            br#"{"tags":["c","a","b"]}"#,
+            &encoder,
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let sim = a.id_to_vec[&0].cosine(&b.id_to_vec[&0]);
+        assert!((sim - 1.0).abs() < 1e-9);
+        assert_eq!(ArrayMode::parse("list"), None);
+    }
+
+    #[test]
+    fn test_numbers_use_similarity_preserving_encoding() {
+        let encoder = EncoderConfig::default();
+        let field = |body: &[u8]| {
+            let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        let a = field(br#"{"magnitude":6.2}"#);
+        let b = field(br#"{"magnitude":6.3}"#);
+        let c = field(br#"{"magnitude":9.8}"#);
+        assert!(a.cosine(&b) > 0.9, "got {}", a.cosine(&b));
+        assert!(a.cosine(&b) > a.cosine(&c));
+    }
+
+    #[test]
+    fn test_numeric_range_is_configurable_per_field() {
+        let mut encoder = EncoderConfig::default();
+        encoder
+            .numeric_ranges
+            .extend(numeric::parse_numeric_ranges("cpu=0..100/101").unwrap());
+        let field = |body: &[u8]| {
+            let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        let idle = field(br#"{"cpu":5}"#);
+        let busy = field(br#"{"cpu":95}"#);
+        assert_eq!(idle.cosine(&busy), 0.0);
+        assert!(idle.cosine(&field(br#"{"cpu":7}"#)) > 0.9);
+    }
+
+    #[test]
+    fn test_type_change_is_a_distinct_deviation() {
+        let encoder = EncoderConfig::default();
+        let field = |body: &[u8]| {
+            let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        let number = field(br#"{"magnitude":6.2}"#);
+        let string = field(br#"{"magnitude":"6.2"}"#);
+        assert!(number.cosine(&string).abs() < 0.2);
+        let null = field(br#"{"flag":null}"#);
+        let falsy = field(br#"{"flag":false}"#);
+        assert!(null.cosine(&falsy).abs() < 0.2);
+
+        let encoded = encode_json_fields(
+            br#"{"a":null,"b":true,"c":"x"}"#,
+            &encoder,
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let mut types: Vec<JsonType> = encoded.id_to_type.into_values().collect();
+        types.sort_by_key(|t| t.name());
+        assert_eq!(types, [JsonType::Bool, JsonType::Null, JsonType::String]);
+    }
+
+    #[test]
+    fn test_night_event_scores_more_novel_on_daytime_stream() {
+        let encoder = EncoderConfig {
+            detect_timestamps: true,
+            ..Default::default()
+        };
+        let bundle = |body: &str| {
+            build_master_bundle(
+                &encode_json_fields(body.as_bytes(), &encoder, &mut FieldIds::default())
+                    .unwrap()
+                    .id_to_vec,
+                &HashMap::new(),
+            )
+            .unwrap()
+        };
+        let baseline = SparseVec::bundle_sum_many(&[
+            bundle(r#"{"event":"login","at":"2024-05-01T09:10:00Z"}"#),
+            bundle(r#"{"event":"login","at":"2024-05-02T13:40:00Z"}"#),
+            bundle(r#"{"event":"login","at":"2024-05-03T16:05:00Z"}"#),
+        ]);
+        let day = bundle(r#"{"event":"login","at":"2024-05-02T14:00:00Z"}"#);
+        let night = bundle(r#"{"event":"login","at":"2024-05-02T03:00:00Z"}"#);
+        let day_score = anomaly::score_against_baseline(&day, &baseline);
+        let night_score = anomaly::score_against_baseline(&night, &baseline);
+        assert!(night_score.novelty > day_score.novelty);
+    }
+
+    #[test]
+    fn test_configured_epoch_timestamp_field() {
+        let mut encoder = EncoderConfig::default();
+        encoder
+            .timestamp_fields
+            .insert("ts".to_string(), TimestampFormat::EpochMillis);
+        let field = |body: &[u8]| {
+            let encoded = encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        // 2023-11-14T22:13:20Z and ten minutes later.
+        let a = field(br#"{"ts":1700000000000}"#);
+        let b = field(br#"{"ts":1700000600000}"#);
+        assert!(a.cosine(&b) > 0.8, "got {}", a.cosine(&b));
+    }
+
+    #[test]
+    fn test_text_encoding_is_selected_per_field() {
+        let mut encoder = EncoderConfig::default();
+        let field = |body: &[u8], encoder: &EncoderConfig| {
+            let encoded = encode_json_fields(body, encoder, &mut FieldIds::default()).unwrap();
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        let short = br#"{"location":"Pacific Ocean"}"#;
+        // A leading word shifts every byte position of the exact encoding.
+        let long = br#"{"location":"South Pacific Ocean"}"#;
+        let exact = field(short, &encoder).cosine(&field(long, &encoder));
+        encoder
+            .string_encodings
+            .insert("location".to_string(), StringEncoding::Text);
+        let text = field(short, &encoder).cosine(&field(long, &encoder));
+        assert!(text > 0.5, "got {text}");
+        assert!(text > exact + 0.2, "text {text} vs exact {exact}");
+    }
+
+    #[test]
+    fn test_excluded_fields_are_not_encoded() {
+        let encoder = EncoderConfig {
+            exclude: vec!["request_id".to_string(), "*.trace_id".to_string()],
+            ..Default::default()
+        };
+        let a = br#"{"status":"ok","request_id":"r-1","meta":{"trace_id":"t-1","host":"a"}}"#;
+        let b = br#"{"status":"ok","request_id":"r-2","meta":{"trace_id":"t-2","host":"a"}}"#;
+        let fields = encode_json_fields(a, &encoder, &mut FieldIds::default()).unwrap();
+        let mut names: Vec<&str> = fields.id_to_field.values().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["meta.host", "status"]);
+        let bundle_a = build_master_bundle(&fields.id_to_vec, &HashMap::new()).unwrap();
+        let bundle_b = build_master_bundle(
+            &encode_json_fields(b, &encoder, &mut FieldIds::default())
+                .unwrap()
+                .id_to_vec,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert!((bundle_a.cosine(&bundle_b) - 1.0).abs() < 1e-9);
+
+        let hierarchical = EncoderConfig {
+            structure: StructureMode::Hierarchical,
+            ..encoder
+        };
+        let a = encode_json_fields(a, &hierarchical, &mut FieldIds::default()).unwrap();
+        let b = encode_json_fields(b, &hierarchical, &mut FieldIds::default()).unwrap();
+        assert_eq!(a.id_to_vec.len(), 2);
+        let bundle_a = build_master_bundle(&a.id_to_vec, &HashMap::new()).unwrap();
+        let bundle_b = build_master_bundle(&b.id_to_vec, &HashMap::new()).unwrap();
+        assert!((bundle_a.cosine(&bundle_b) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_categorical_numbers_are_exact() {
+        let mut encoder = EncoderConfig::default();
+        let field = |body: &[u8], encoder: &EncoderConfig| {
+            let encoded = encode_json_fields(body, encoder, &mut FieldIds::default()).unwrap();
+            encoded.id_to_vec.into_values().next().unwrap()
+        };
+        let levels =
+            field(br#"{"code":200}"#, &encoder).cosine(&field(br#"{"code":201}"#, &encoder));
+        encoder.categorical_fields.insert("code".to_string());
+        let exact =
+            field(br#"{"code":200}"#, &encoder).cosine(&field(br#"{"code":201}"#, &encoder));
+        assert!(levels > 0.9, "got {levels}");
+        assert!(exact < levels, "got {exact}");
+    }
+
     #[test]
     fn test_encode_fields_rejects_json_array() {
-        let result = encode_json_fields(b"[1, 2, 3]");
+        let result = encode_json_fields(
+            b"[1, 2, 3]",
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        );
         assert!(result.is_err());
         assert!(
             result.err().unwrap().contains("not a JSON object"),
@@ -222,7 +2141,11 @@ mod tests {
 
     #[test]
     fn test_encode_fields_rejects_invalid_json() {
-        let result = encode_json_fields(b"not json");
+        let result = encode_json_fields(
+            b"not json",
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        );
         assert!(result.is_err());
         assert!(
             result.err().unwrap().contains("JSON parse error"),
@@ -232,14 +2155,23 @@ mod tests {
 
     #[test]
     fn test_encode_fields_rejects_json_string() {
-        let result = encode_json_fields(br#""just a string""#);
+        let result = encode_json_fields(
+            br#""just a string""#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        );
         assert!(result.is_err());
     }
 
     #[test]
     fn test_build_master_bundle_single_field() {
-        let encoded = encode_json_fields(br#"{"only":"field"}"#).unwrap();
-        let bundle = build_master_bundle(&encoded.id_to_vec);
+        let encoded = encode_json_fields(
+            br#"{"only":"field"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let bundle = build_master_bundle(&encoded.id_to_vec, &HashMap::new());
         assert!(
             bundle.is_some(),
             "bundle should exist for a single-field object"
@@ -248,30 +2180,63 @@ mod tests {
 
     #[test]
     fn test_build_master_bundle_multiple_fields() {
-        let encoded = encode_json_fields(br#"{"a":"1","b":"2","c":"3"}"#).unwrap();
-        let bundle = build_master_bundle(&encoded.id_to_vec);
+        let encoded = encode_json_fields(
+            br#"{"a":"1","b":"2","c":"3"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
+        let bundle = build_master_bundle(&encoded.id_to_vec, &HashMap::new());
         assert!(
             bundle.is_some(),
             "bundle should exist for a multi-field object"
         );
     }
 
+    #[test]
+    fn test_weighted_bundle_scores_reflect_field_importance() {
+        let encoder = EncoderConfig::default();
+        let encoded =
+            |body: &[u8]| encode_json_fields(body, &encoder, &mut FieldIds::default()).unwrap();
+        let base = encoded(br#"{"status":"ok","host":"web-1","region":"pacific"}"#);
+        let status_changed = encoded(br#"{"status":"failed","host":"web-1","region":"pacific"}"#);
+        let host_changed = encoded(br#"{"status":"ok","host":"web-9","region":"pacific"}"#);
+        let weights = |fields: &EncodedFields| {
+            weights::resolve_weights(&fields.id_to_field, &[("status".to_string(), 4.0)], None)
+        };
+        let bundle = |fields: &EncodedFields| {
+            build_master_bundle(&fields.id_to_vec, &weights(fields)).unwrap()
+        };
+        let baseline = bundle(&base);
+        let status_score = anomaly::score_against_baseline(&bundle(&status_changed), &baseline);
+        let host_score = anomaly::score_against_baseline(&bundle(&host_changed), &baseline);
+        assert!(
+            status_score.novelty > host_score.novelty + 0.2,
+            "status {status_score:?} vs host {host_score:?}"
+        );
+    }
+
     #[test]
     fn test_build_master_bundle_empty_map() {
         let empty: HashMap<usize, SparseVec> = HashMap::new();
-        let bundle = build_master_bundle(&empty);
+        let bundle = build_master_bundle(&empty, &HashMap::new());
         assert!(bundle.is_none(), "empty map should yield no bundle");
     }
 
     #[test]
     fn test_serialise_vector_roundtrip() {
-        let encoded = encode_json_fields(br#"{"sensor":"temperature","value":"42.5"}"#).unwrap();
+        let encoded = encode_json_fields(
+            br#"{"sensor":"temperature","value":"42.5"}"#,
+            &EncoderConfig::default(),
+            &mut FieldIds::default(),
+        )
+        .unwrap();
         let original = encoded.id_to_vec.values().next().unwrap();
         let bytes = serialise_vector(original).unwrap();
         assert!(!bytes.is_empty(), "serialised bytes must not be empty");
         // Deserialise then re-serialise: bytes must be identical (bincode is deterministic)
-        let restored: SparseVec =
-            from_bincode(&bytes).expect("from_bincode should succeed on a serialised SparseVec");
+        let restored = deserialise_vector(&bytes)
+            .expect("deserialisation should succeed on a serialised SparseVec");
         let bytes2 = serialise_vector(&restored).expect("re-serialisation should succeed");
         assert_eq!(
             bytes, bytes2,
@@ -283,8 +2248,10 @@ mod tests {
     fn test_same_input_produces_same_vector() {
         // from_data is deterministic: same bytes -> same serialised vector
         let body = br#"{"key":"value"}"#;
-        let enc1 = encode_json_fields(body).unwrap();
-        let enc2 = encode_json_fields(body).unwrap();
+        let enc1 =
+            encode_json_fields(body, &EncoderConfig::default(), &mut FieldIds::default()).unwrap();
+        let enc2 =
+            encode_json_fields(body, &EncoderConfig::default(), &mut FieldIds::default()).unwrap();
         let bytes1 = serialise_vector(enc1.id_to_vec.values().next().unwrap()).unwrap();
         let bytes2 = serialise_vector(enc2.id_to_vec.values().next().unwrap()).unwrap();
         assert_eq!(
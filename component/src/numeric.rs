@@ -0,0 +1,193 @@
+//! Similarity-preserving encoding of numbers.
+//!
+//! A number is quantised to one of `resolution` levels across its range and
+//! encoded as a sliding window of [`WINDOW`] consecutive indices starting at
+//! that level. Neighbouring levels share all but one index, so cosine
+//! similarity falls off linearly with distance and reaches zero once two
+//! values are `WINDOW` levels apart: `6.2` and `6.3` stay near-identical,
+//! while `6` and `600` are unrelated.
+
+use crate::symbols::label_hash;
+use embeddenator_vsa::{SparseVec, DIM};
+use std::collections::HashMap;
+
+/// Non-zero indices in an encoded number; also the number of levels over
+/// which similarity decays to zero.
+pub(crate) const WINDOW: usize = 32;
+
+/// How raw values are mapped onto the range before quantisation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NumericScale {
+    Linear,
+    /// `sign(x) · log10(1 + |x|)`, for values spanning orders of magnitude.
+    Log,
+}
+
+/// Range and resolution used to encode one numeric field.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct NumericRange {
+    /// Lower bound, in scaled units; smaller values saturate.
+    pub min: f64,
+    /// Upper bound, in scaled units; larger values saturate.
+    pub max: f64,
+    /// Number of quantisation levels between `min` and `max`.
+    pub resolution: usize,
+    pub scale: NumericScale,
+}
+
+impl NumericRange {
+    /// Quantisation level of `value`, in `0..resolution`.
+    pub(crate) fn level(&self, value: f64) -> usize {
+        let x = match self.scale {
+            NumericScale::Linear => value,
+            NumericScale::Log => value.signum() * value.abs().ln_1p() / std::f64::consts::LN_10,
+        };
+        let span = self.max - self.min;
+        let frac = if span > 0.0 {
+            ((x - self.min) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (frac * self.resolution.saturating_sub(1) as f64).round() as usize
+    }
+}
+
+impl Default for NumericRange {
+    /// Log scale over ±10¹², fine enough that values within ~5% stay highly
+    /// similar, for fields without a configured range.
+    fn default() -> Self {
+        Self {
+            min: -12.0,
+            max: 12.0,
+            resolution: 1024,
+            scale: NumericScale::Log,
+        }
+    }
+}
+
+/// Default resolution for ranges given without one in a spec.
+pub(crate) const DEFAULT_RESOLUTION: usize = 256;
+
+/// Largest accepted resolution: the window of the highest level must still
+/// fit in [`DIM`] indices.
+pub(crate) const MAX_RESOLUTION: usize = DIM - WINDOW;
+
+/// Parse per-field ranges from a comma-separated spec of
+/// `path=min..max[/resolution][@log]` entries, e.g.
+/// `cpu=0..100/101,latency_ms=0..6@log`. With `@log`, bounds are in
+/// `log10` units. Resolutions must be in `2..=MAX_RESOLUTION`. An empty spec
+/// yields no ranges.
+pub(crate) fn parse_numeric_ranges(spec: &str) -> Result<HashMap<String, NumericRange>, String> {
+    let mut ranges = HashMap::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let err = || format!("invalid numeric range '{entry}'");
+        let (path, range) = entry.split_once('=').ok_or_else(err)?;
+        let (range, scale) = match range.strip_suffix("@log") {
+            Some(range) => (range, NumericScale::Log),
+            None => (range, NumericScale::Linear),
+        };
+        let (bounds, resolution) = match range.split_once('/') {
+            Some((bounds, res)) => (bounds, res.trim().parse().map_err(|_| err())?),
+            None => (range, DEFAULT_RESOLUTION),
+        };
+        let (min, max) = bounds.split_once("..").ok_or_else(err)?;
+        let min: f64 = min.trim().parse().map_err(|_| err())?;
+        let max: f64 = max.trim().parse().map_err(|_| err())?;
+        if max <= min || !(2..=MAX_RESOLUTION).contains(&resolution) {
+            return Err(err());
+        }
+        ranges.insert(
+            path.trim().to_string(),
+            NumericRange {
+                min,
+                max,
+                resolution,
+                scale,
+            },
+        );
+    }
+    Ok(ranges)
+}
+
+/// Encode `value` for the field labelled `label`. The label only offsets
+/// the window within the vector so different numeric fields rarely overlap.
+pub(crate) fn encode_number(value: f64, range: &NumericRange, label: &str) -> SparseVec {
+    let start = (label_hash(label) as usize % DIM) + range.level(value);
+    let mut pos: Vec<usize> = (start..start + WINDOW).map(|idx| idx % DIM).collect();
+    pos.sort_unstable();
+    SparseVec {
+        pos,
+        neg: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear(min: f64, max: f64, resolution: usize) -> NumericRange {
+        NumericRange {
+            min,
+            max,
+            resolution,
+            scale: NumericScale::Linear,
+        }
+    }
+
+    fn sim(a: f64, b: f64, range: &NumericRange) -> f64 {
+        encode_number(a, range, "magnitude").cosine(&encode_number(b, range, "magnitude"))
+    }
+
+    #[test]
+    fn test_nearby_values_are_similar_by_default() {
+        let range = NumericRange::default();
+        assert!(sim(6.2, 6.3, &range) > 0.9, "got {}", sim(6.2, 6.3, &range));
+        assert!(sim(6.2, 6.3, &range) > sim(6.2, 60.0, &range));
+        assert!(sim(6.0, 600.0, &range) < 0.1);
+    }
+
+    #[test]
+    fn test_similarity_decays_linearly_within_linear_range() {
+        let range = linear(0.0, 100.0, 101);
+        let near = sim(50.0, 51.0, &range);
+        let mid = sim(50.0, 58.0, &range);
+        let far = sim(50.0, 90.0, &range);
+        assert!((near - 31.0 / 32.0).abs() < 1e-9);
+        assert!((mid - 24.0 / 32.0).abs() < 1e-9);
+        assert_eq!(far, 0.0);
+    }
+
+    #[test]
+    fn test_out_of_range_values_saturate() {
+        let range = linear(0.0, 10.0, 11);
+        assert_eq!(range.level(-5.0), 0);
+        assert_eq!(range.level(50.0), 10);
+        assert!((sim(10.0, 50.0, &range) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_numeric_ranges() {
+        let ranges =
+            parse_numeric_ranges("cpu=0..100/101, latency_ms=0..6@log,temp=-40..60").unwrap();
+        assert_eq!(ranges["cpu"], linear(0.0, 100.0, 101));
+        assert_eq!(ranges["latency_ms"].scale, NumericScale::Log);
+        assert_eq!(ranges["latency_ms"].resolution, DEFAULT_RESOLUTION);
+        assert_eq!(ranges["temp"].min, -40.0);
+        assert!(parse_numeric_ranges("").unwrap().is_empty());
+        assert!(parse_numeric_ranges("cpu=100..0").is_err());
+        assert!(parse_numeric_ranges("cpu").is_err());
+        assert!(parse_numeric_ranges("cpu=0..1/x").is_err());
+        assert!(parse_numeric_ranges("cpu=0..1/0").is_err());
+        assert!(parse_numeric_ranges("cpu=0..1/1").is_err());
+        assert!(parse_numeric_ranges(&format!("cpu=0..1/{MAX_RESOLUTION}")).is_ok());
+        assert!(parse_numeric_ranges(&format!("cpu=0..1/{}", MAX_RESOLUTION + 1)).is_err());
+        assert!(parse_numeric_ranges("cpu=0..1/18446744073709551615").is_err());
+    }
+
+    #[test]
+    fn test_negative_values_on_log_scale() {
+        let range = NumericRange::default();
+        assert!(range.level(-100.0) < range.level(0.0));
+        assert!(range.level(0.0) < range.level(100.0));
+    }
+}
@@ -0,0 +1,356 @@
+//! Online clustering of messages into pattern prototypes.
+//!
+//! One baseline per subject assumes the subject has a single kind of
+//! message. Streams with several legitimate shapes (orders created, paid,
+//! shipped, ...) instead keep a set of prototypes per subject, stored under
+//! `prototypes:v2:{subject}` as bincode. Each prototype has a stable id, a
+//! message count and the [`BundleVotes`] of its messages. Its centroid is the
+//! thresholded votes, so it keeps following new messages however many the
+//! prototype already holds.
+//!
+//! A message joins the most similar prototype, or spawns a new one when no
+//! prototype reaches `prototype_spawn_threshold`. Prototypes that grow to
+//! within `prototype_merge_threshold` of each other are merged, the larger
+//! one keeping its id. When `prototype_limit` prototypes exist, the two
+//! closest are merged to make room for a new one.
+
+use crate::anomaly::similarity;
+use crate::baseline::BundleVotes;
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+
+/// Default number of prototypes kept per subject.
+pub(crate) const DEFAULT_PROTOTYPE_LIMIT: usize = 16;
+
+/// Default similarity below which a message spawns a new prototype.
+pub(crate) const DEFAULT_SPAWN_THRESHOLD: f64 = 0.5;
+
+/// Default similarity at or above which two prototypes are merged.
+pub(crate) const DEFAULT_MERGE_THRESHOLD: f64 = 0.85;
+
+/// One learned message shape.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Prototype {
+    pub id: u64,
+    /// Messages assigned to the prototype, including merged ones.
+    pub count: u64,
+    /// Unix epoch milliseconds of the first message.
+    pub created_ms: u64,
+    /// Unix epoch milliseconds of the latest message.
+    pub last_seen_ms: u64,
+    /// Votes of the prototype's messages.
+    pub votes: BundleVotes,
+    /// Thresholded votes, kept in step with them.
+    pub centroid: SparseVec,
+}
+
+impl Prototype {
+    fn new(id: u64, message: &SparseVec, at_ms: u64) -> Self {
+        let mut prototype = Self {
+            id,
+            count: 0,
+            created_ms: at_ms,
+            last_seen_ms: at_ms,
+            votes: BundleVotes::default(),
+            centroid: SparseVec::new(),
+        };
+        prototype.add(message, at_ms);
+        prototype
+    }
+
+    /// Count `message` in the prototype.
+    fn add(&mut self, message: &SparseVec, at_ms: u64) {
+        self.votes.add(message);
+        self.count += 1;
+        self.last_seen_ms = self.last_seen_ms.max(at_ms);
+        self.centroid = self.votes.bundle();
+    }
+
+    /// Fold `other`'s messages into the prototype.
+    fn absorb(&mut self, other: Self) {
+        self.votes.absorb(&other.votes);
+        self.count += other.count;
+        self.created_ms = self.created_ms.min(other.created_ms);
+        self.last_seen_ms = self.last_seen_ms.max(other.last_seen_ms);
+        self.centroid = self.votes.bundle();
+    }
+
+    /// Whether `self` outranks `other` when both are merged: the larger
+    /// prototype survives, the older one among equals.
+    fn outranks(&self, other: &Self) -> bool {
+        (self.count, std::cmp::Reverse(self.id)) > (other.count, std::cmp::Reverse(other.id))
+    }
+}
+
+/// Where a message was placed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Assignment {
+    /// Id of the prototype now holding the message.
+    pub prototype: u64,
+    /// Similarity of the message to the closest prototype before it was
+    /// placed; 0 for the first message of a subject.
+    pub similarity: f64,
+    /// Whether the message spawned a new prototype.
+    pub spawned: bool,
+    /// Ids of prototypes merged away while placing the message.
+    pub merged: Vec<u64>,
+}
+
+/// A prototype as listed over HTTP, without its centroid.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct PrototypeSummary {
+    pub id: u64,
+    pub count: u64,
+    pub created_ms: u64,
+    pub last_seen_ms: u64,
+}
+
+/// The prototypes of one subject.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Prototypes {
+    /// Id given to the next spawned prototype.
+    pub next_id: u64,
+    pub prototypes: Vec<Prototype>,
+}
+
+impl Prototypes {
+    /// Parse prototypes read from the bucket.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes).map_err(|e| format!("prototypes decode error: {e}"))
+    }
+
+    /// Serialise the prototypes for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("prototypes encode error: {e}"))
+    }
+
+    /// Place `message` in the closest prototype or a new one (see the module
+    /// docs), keeping at most `limit` prototypes.
+    pub(crate) fn assign(
+        &mut self,
+        message: &SparseVec,
+        at_ms: u64,
+        limit: usize,
+        spawn_threshold: f64,
+        merge_threshold: f64,
+    ) -> Assignment {
+        let nearest = self
+            .prototypes
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, similarity(message, &p.centroid)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let mut merged = Vec::new();
+        let (mut index, spawned) = match nearest {
+            // With room for a single prototype, everything joins it.
+            Some((i, sim)) if sim >= spawn_threshold || limit <= 1 => {
+                self.prototypes[i].add(message, at_ms);
+                (i, false)
+            }
+            _ => {
+                if self.prototypes.len() >= limit {
+                    if let Some((a, b)) = self.closest_pair() {
+                        merged.push(self.merge(a, b).1);
+                    }
+                }
+                self.prototypes
+                    .push(Prototype::new(self.next_id, message, at_ms));
+                self.next_id += 1;
+                (self.prototypes.len() - 1, true)
+            }
+        };
+
+        // The touched prototype may now sit close enough to others to merge.
+        while let Some(other) = (0..self.prototypes.len()).find(|&j| {
+            j != index
+                && similarity(
+                    &self.prototypes[index].centroid,
+                    &self.prototypes[j].centroid,
+                ) >= merge_threshold
+        }) {
+            let (kept, absorbed) = self.merge(index, other);
+            merged.push(absorbed);
+            index = kept;
+        }
+
+        Assignment {
+            prototype: self.prototypes[index].id,
+            similarity: nearest.map(|(_, sim)| sim).unwrap_or(0.0),
+            spawned,
+            merged,
+        }
+    }
+
+    /// Prototypes without centroids, most messages first.
+    pub(crate) fn summaries(&self) -> Vec<PrototypeSummary> {
+        let mut summaries: Vec<PrototypeSummary> = self
+            .prototypes
+            .iter()
+            .map(|p| PrototypeSummary {
+                id: p.id,
+                count: p.count,
+                created_ms: p.created_ms,
+                last_seen_ms: p.last_seen_ms,
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.id.cmp(&b.id)));
+        summaries
+    }
+
+    /// Indices of the two most similar prototypes.
+    fn closest_pair(&self) -> Option<(usize, usize)> {
+        let n = self.prototypes.len();
+        (0..n)
+            .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+            .max_by(|&(a1, b1), &(a2, b2)| {
+                let sim = |a: usize, b: usize| {
+                    similarity(&self.prototypes[a].centroid, &self.prototypes[b].centroid)
+                };
+                sim(a1, b1).total_cmp(&sim(a2, b2))
+            })
+    }
+
+    /// Merge prototypes `a` and `b`. Returns the index of the survivor after
+    /// the merge and the id of the absorbed prototype.
+    fn merge(&mut self, a: usize, b: usize) -> (usize, u64) {
+        let (keep, absorb) = if self.prototypes[a].outranks(&self.prototypes[b]) {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let absorbed = self.prototypes.remove(absorb);
+        let keep = if absorb < keep { keep - 1 } else { keep };
+        let id = absorbed.id;
+        self.prototypes[keep].absorb(absorbed);
+        (keep, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field_ids::FieldIds;
+    use crate::{build_master_bundle, encode_json_fields, EncoderConfig};
+    use std::collections::HashMap;
+
+    fn bundle_of(body: &[u8]) -> SparseVec {
+        let encoded =
+            encode_json_fields(body, &EncoderConfig::default(), &mut FieldIds::default()).unwrap();
+        build_master_bundle(&encoded.id_to_vec, &HashMap::new()).unwrap()
+    }
+
+    fn assign(prototypes: &mut Prototypes, body: &[u8], at_ms: u64) -> Assignment {
+        prototypes.assign(&bundle_of(body), at_ms, 4, 0.5, 0.85)
+    }
+
+    #[test]
+    fn test_each_message_shape_gets_its_own_prototype() {
+        let mut prototypes = Prototypes::default();
+        let created = br#"{"kind":"created","order":"o-1","items":3}"#;
+        let paid = br#"{"kind":"paid","amount":"12.50","card":"visa"}"#;
+        let first = assign(&mut prototypes, created, 1);
+        assert!(first.spawned);
+        assert_eq!(first.prototype, 0);
+        let second = assign(&mut prototypes, paid, 2);
+        assert!(second.spawned);
+        assert_eq!(second.prototype, 1);
+
+        let again = assign(
+            &mut prototypes,
+            br#"{"kind":"created","order":"o-2","items":3}"#,
+            3,
+        );
+        assert!(!again.spawned);
+        assert_eq!(again.prototype, 0);
+        assert!(again.similarity >= 0.5, "got {}", again.similarity);
+
+        let summaries = prototypes.summaries();
+        assert_eq!(summaries[0].id, 0);
+        assert_eq!(summaries[0].count, 2);
+        assert_eq!(summaries[0].last_seen_ms, 3);
+        assert_eq!(summaries[1].count, 1);
+    }
+
+    #[test]
+    fn test_close_prototypes_merge_into_the_larger() {
+        let mut prototypes = Prototypes::default();
+        let a = sparse_code("a");
+        prototypes.assign(&a, 0, 4, 0.5, 0.85);
+        prototypes.assign(&a, 1, 4, 0.5, 0.85);
+        // Spawn with a high threshold, then merge it back in.
+        let spawned = prototypes.assign(&a, 2, 4, 1.1, 0.85);
+        assert!(spawned.spawned);
+        assert_eq!(spawned.merged, [1]);
+        assert_eq!(spawned.prototype, 0);
+        assert_eq!(prototypes.prototypes.len(), 1);
+        assert_eq!(prototypes.prototypes[0].count, 3);
+    }
+
+    #[test]
+    fn test_closest_pair_makes_room_at_the_limit() {
+        let mut prototypes = Prototypes::default();
+        for (at, label) in ["a", "b", "c"].iter().enumerate() {
+            prototypes.assign(&sparse_code(label), at as u64, 2, 0.5, 0.99);
+        }
+        assert_eq!(prototypes.prototypes.len(), 2);
+        assert_eq!(prototypes.next_id, 3);
+        assert_eq!(prototypes.prototypes.last().unwrap().id, 2);
+
+        let restored = Prototypes::from_bytes(&prototypes.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.next_id, 3);
+        assert_eq!(restored.summaries(), prototypes.summaries());
+    }
+
+    #[test]
+    fn test_centroid_keeps_following_a_large_prototype() {
+        let mut prototypes = Prototypes::default();
+        let (old, new) = (sparse_code("old"), sparse_code("new"));
+        for at in 0..80 {
+            prototypes.assign(&old, at, 1, 0.5, 0.85);
+        }
+        for at in 80..500 {
+            prototypes.assign(&new, at, 1, 0.5, 0.85);
+        }
+        // "old" now holds under a quarter of the votes of "new" and drops out.
+        let centroid = &prototypes.prototypes[0].centroid;
+        assert!(
+            centroid.cosine(&new) > 0.99,
+            "got {}",
+            centroid.cosine(&new)
+        );
+        assert!(centroid.cosine(&old) < 0.1);
+        assert_eq!(prototypes.prototypes[0].count, 500);
+    }
+
+    #[test]
+    fn test_merge_sums_the_votes() {
+        let (a, b) = (sparse_code("a"), sparse_code("b"));
+        let mut prototypes = Prototypes::default();
+        for at in 0..30 {
+            prototypes.assign(&a, at, 4, 0.5, 1.1);
+        }
+        for at in 30..40 {
+            prototypes.assign(&b, at, 4, 0.5, 1.1);
+        }
+        let (kept, absorbed) = prototypes.merge(0, 1);
+        assert_eq!(absorbed, 1);
+        let prototype = &prototypes.prototypes[kept];
+        assert_eq!(prototype.count, 40);
+        assert_eq!((prototype.created_ms, prototype.last_seen_ms), (0, 39));
+        for dim in a
+            .pos
+            .iter()
+            .filter(|dim| !b.pos.contains(dim) && !b.neg.contains(dim))
+        {
+            assert_eq!(prototype.votes.0[dim], 30);
+        }
+        // Ten messages of "b" are a third of the 30 of "a": both are kept.
+        assert!(prototype.centroid.cosine(&a) > 0.5);
+        assert!(prototype.centroid.cosine(&b) > 0.3);
+    }
+
+    fn sparse_code(label: &str) -> SparseVec {
+        crate::symbols::sparse_code(label, 200)
+    }
+}
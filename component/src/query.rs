@@ -0,0 +1,201 @@
+//! Request-reply similarity queries.
+//!
+//! A client publishes a request on a subject matching `query_subject`
+//! (`pattern.monitor.query.>` by default) with a reply subject:
+//!
+//! ```json
+//! { "example": { "status": "failed", "region": "eu" }, "subject": "pattern.monitor.auth", "k": 5 }
+//! ```
+//!
+//! The example is encoded like an incoming message. With a `subject`, the
+//! reply ranks that subject's stored messages (see [`crate::history`]);
+//! without one, it ranks every subject by the similarity of the example to
+//! the subject's master bundle. Each match explains itself with the fields
+//! of the example that deviate most from the matched bundle. Failures are
+//! answered with `{"error": ...}` so clients are not left waiting.
+//!
+//! [`ScoreRequest`] scores a message against a subject's baseline; it is
+//! served over HTTP only (see [`crate::routes`]).
+
+use crate::anomaly::{AnomalyScore, FieldDeviation};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Default subject pattern queries are received on.
+pub(crate) const DEFAULT_QUERY_SUBJECT: &str = "pattern.monitor.query.>";
+
+/// Matches returned when a request does not set `k`.
+pub(crate) const DEFAULT_QUERY_K: usize = 5;
+
+/// A query request.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct QueryRequest {
+    /// Example message, encoded like an incoming one.
+    pub example: Map<String, Value>,
+    /// Subject whose stored messages are searched; all subjects' bundles are
+    /// ranked if absent.
+    pub subject: Option<String>,
+    /// Number of matches to return.
+    #[serde(default = "default_k")]
+    pub k: usize,
+}
+
+fn default_k() -> usize {
+    DEFAULT_QUERY_K
+}
+
+impl QueryRequest {
+    /// Parse a request body.
+    pub(crate) fn parse(body: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(body).map_err(|e| format!("query parse error: {e}"))
+    }
+
+    /// The example re-serialised as a message body.
+    pub(crate) fn example_body(&self) -> Vec<u8> {
+        Value::Object(self.example.clone()).to_string().into_bytes()
+    }
+}
+
+/// A request to score a message against a subject's baseline without
+/// folding it in.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ScoreRequest {
+    pub subject: String,
+    pub message: Map<String, Value>,
+}
+
+impl ScoreRequest {
+    /// Parse a request body.
+    pub(crate) fn parse(body: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(body).map_err(|e| format!("score request parse error: {e}"))
+    }
+
+    /// The message re-serialised as a message body.
+    pub(crate) fn message_body(&self) -> Vec<u8> {
+        Value::Object(self.message.clone()).to_string().into_bytes()
+    }
+}
+
+/// Answer to a [`ScoreRequest`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ScoreResponse {
+    pub subject: String,
+    pub score: AnomalyScore,
+    /// Every field's similarity to the baseline, most deviating first.
+    pub fields: Vec<FieldDeviation>,
+}
+
+/// One ranked match: a subject, or a stored message of a subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct QueryMatch {
+    pub subject: String,
+    /// Sequence number of the stored message; absent for subject matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<u64>,
+    /// Unix epoch milliseconds at which the message was stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Cosine similarity to the example.
+    pub score: f64,
+    /// How each field of the example deviates from the matched bundle, the
+    /// largest share first (at most `alert_top_fields` of them).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldDeviation>,
+}
+
+/// Reply published to the request's reply subject.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum QueryResponse {
+    Matches { matches: Vec<QueryMatch> },
+    Error { error: String },
+}
+
+impl QueryResponse {
+    /// The `k` best of `matches`, most similar first.
+    pub(crate) fn top_k(mut matches: Vec<QueryMatch>, k: usize) -> Self {
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.subject.cmp(&b.subject))
+                .then_with(|| b.message.cmp(&a.message))
+        });
+        matches.truncate(k);
+        Self::Matches { matches }
+    }
+
+    /// Serialise the reply as a message body.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("query reply encode error: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subject_match(subject: &str, score: f64) -> QueryMatch {
+        QueryMatch {
+            subject: subject.to_string(),
+            message: None,
+            timestamp: None,
+            score,
+            fields: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request =
+            QueryRequest::parse(br#"{"example":{"status":"failed"},"subject":"a.b"}"#).unwrap();
+        assert_eq!(request.subject.as_deref(), Some("a.b"));
+        assert_eq!(request.k, DEFAULT_QUERY_K);
+        assert_eq!(request.example_body(), br#"{"status":"failed"}"#);
+        assert!(QueryRequest::parse(br#"{"example":[1]}"#).is_err());
+        assert!(QueryRequest::parse(br#"{"status":"failed"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_score_request() {
+        let request =
+            ScoreRequest::parse(br#"{"subject":"a.b","message":{"status":"ok"}}"#).unwrap();
+        assert_eq!(request.subject, "a.b");
+        assert_eq!(request.message_body(), br#"{"status":"ok"}"#);
+        assert!(ScoreRequest::parse(br#"{"message":{"status":"ok"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_top_k_ranks_best_first() {
+        let response = QueryResponse::top_k(
+            vec![
+                subject_match("a", 0.2),
+                subject_match("b", 0.9),
+                subject_match("c", 0.5),
+            ],
+            2,
+        );
+        let QueryResponse::Matches { matches } = response else {
+            panic!("expected matches");
+        };
+        let subjects: Vec<&str> = matches.iter().map(|m| m.subject.as_str()).collect();
+        assert_eq!(subjects, ["b", "c"]);
+    }
+
+    #[test]
+    fn test_reply_json_shapes() {
+        let matches = QueryResponse::top_k(vec![subject_match("a", 0.5)], 5);
+        assert_eq!(
+            String::from_utf8(matches.to_json().unwrap()).unwrap(),
+            r#"{"matches":[{"subject":"a","score":0.5}]}"#
+        );
+        let error = QueryResponse::Error {
+            error: "bad".to_string(),
+        };
+        assert_eq!(
+            String::from_utf8(error.to_json().unwrap()).unwrap(),
+            r#"{"error":"bad"}"#
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! REST routes served through `wasi:http/incoming-handler`.
+//!
+//! | Route | Answer |
+//! |-------|--------|
+//! | `GET /subjects` | `{"subjects": [...]}`, every subject with a bundle |
+//! | `GET /subjects/{subject}/bundle` | the subject's bundle metadata and score statistics |
+//! | `GET /subjects/{subject}/windows` | the subject's time windows, each compared with the newest |
+//! | `GET /subjects/{subject}/prototypes` | the subject's pattern prototypes, most messages first |
+//! | `GET /subjects/{subject}/sequences` | the subject's known sequence patterns |
+//! | `POST /subjects/{subject}/sequences` | a [`crate::sequence_patterns::SequenceRegistration`] registered as a named pattern |
+//! | `GET /subjects/{subject}/values/{field}` | the field's known values ranked by how strongly the bundle holds them |
+//! | `POST /similar` | a [`crate::query::QueryRequest`] answered like a messaging query |
+//! | `POST /score` | a [`crate::query::ScoreRequest`] scored against the subject's baseline |
+//!
+//! Subjects in paths may be percent-encoded. Errors are answered with
+//! `{"error": ...}` and a matching status code.
+
+/// Largest request body accepted, in bytes.
+#[cfg(not(test))]
+pub(crate) const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// A recognised request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Route {
+    Subjects,
+    Bundle(String),
+    Windows(String),
+    Prototypes(String),
+    Sequences(String),
+    RegisterSequence(String),
+    /// Subject and field.
+    Values(String, String),
+    Similar,
+    Score,
+}
+
+/// A request that could not be answered, as an HTTP status and message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RouteError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl Route {
+    /// Route a request by method and path (a query string is ignored).
+    pub(crate) fn parse(method: &str, path: &str) -> Result<Self, RouteError> {
+        let path = path.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let (route, allowed) = match segments.as_slice() {
+            ["subjects"] => (Self::Subjects, "GET"),
+            ["subjects", subject, "bundle"] if !subject.is_empty() => {
+                (Self::Bundle(percent_decode(subject)?), "GET")
+            }
+            ["subjects", subject, "windows"] if !subject.is_empty() => {
+                (Self::Windows(percent_decode(subject)?), "GET")
+            }
+            ["subjects", subject, "prototypes"] if !subject.is_empty() => {
+                (Self::Prototypes(percent_decode(subject)?), "GET")
+            }
+            ["subjects", subject, "sequences"] if !subject.is_empty() => {
+                let subject = percent_decode(subject)?;
+                match method {
+                    "POST" => (Self::RegisterSequence(subject), "POST"),
+                    _ => (Self::Sequences(subject), "GET"),
+                }
+            }
+            ["subjects", subject, "values", field] if !subject.is_empty() && !field.is_empty() => (
+                Self::Values(percent_decode(subject)?, percent_decode(field)?),
+                "GET",
+            ),
+            ["similar"] => (Self::Similar, "POST"),
+            ["score"] => (Self::Score, "POST"),
+            _ => {
+                return Err(RouteError {
+                    status: 404,
+                    message: format!("no route for '{path}'"),
+                })
+            }
+        };
+        if method != allowed {
+            return Err(RouteError {
+                status: 405,
+                message: format!("'{path}' expects {allowed}, not {method}"),
+            });
+        }
+        Ok(route)
+    }
+}
+
+/// Decode `%XX` escapes in a path segment.
+fn percent_decode(segment: &str) -> Result<String, RouteError> {
+    let invalid = || RouteError {
+        status: 400,
+        message: format!("invalid path segment '{segment}'"),
+    };
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // `from_str_radix` alone would also take a sign, as in `%+1`.
+            let hex = segment
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(invalid)?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes() {
+        assert_eq!(Route::parse("GET", "/subjects"), Ok(Route::Subjects));
+        assert_eq!(Route::parse("GET", "/subjects/"), Ok(Route::Subjects));
+        assert_eq!(
+            Route::parse("GET", "/subjects/pattern.monitor.auth/bundle?pretty"),
+            Ok(Route::Bundle("pattern.monitor.auth".to_string()))
+        );
+        assert_eq!(
+            Route::parse("GET", "/subjects/orders/values/items%5B0%5D.sku"),
+            Ok(Route::Values(
+                "orders".to_string(),
+                "items[0].sku".to_string()
+            ))
+        );
+        assert_eq!(
+            Route::parse("GET", "/subjects/orders/windows"),
+            Ok(Route::Windows("orders".to_string()))
+        );
+        assert_eq!(
+            Route::parse("GET", "/subjects/orders/prototypes"),
+            Ok(Route::Prototypes("orders".to_string()))
+        );
+        assert_eq!(
+            Route::parse("GET", "/subjects/auth/sequences"),
+            Ok(Route::Sequences("auth".to_string()))
+        );
+        assert_eq!(
+            Route::parse("POST", "/subjects/auth/sequences"),
+            Ok(Route::RegisterSequence("auth".to_string()))
+        );
+        assert_eq!(Route::parse("POST", "/similar"), Ok(Route::Similar));
+        assert_eq!(Route::parse("POST", "/score"), Ok(Route::Score));
+    }
+
+    #[test]
+    fn test_route_errors() {
+        assert_eq!(Route::parse("GET", "/nowhere").unwrap_err().status, 404);
+        assert_eq!(
+            Route::parse("GET", "/subjects//bundle").unwrap_err().status,
+            404
+        );
+        assert_eq!(Route::parse("GET", "/score").unwrap_err().status, 405);
+        assert_eq!(Route::parse("POST", "/subjects").unwrap_err().status, 405);
+        assert_eq!(
+            Route::parse("PUT", "/subjects/auth/sequences")
+                .unwrap_err()
+                .status,
+            405
+        );
+    }
+
+    #[test]
+    fn test_subjects_are_percent_decoded() {
+        assert_eq!(
+            Route::parse("GET", "/subjects/orders%2Eeu%20west/bundle"),
+            Ok(Route::Bundle("orders.eu west".to_string()))
+        );
+        for bad in ["bad%zz", "bad%+1", "bad%-1", "bad%2"] {
+            assert_eq!(
+                Route::parse("GET", &format!("/subjects/{bad}/bundle"))
+                    .unwrap_err()
+                    .status,
+                400,
+                "{bad}"
+            );
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Declarative per-subject field schema.
+//!
+//! A schema document lists subject patterns, each with the fields to include
+//! or exclude and the encoder to use per field:
+//!
+//! ```json
+//! { "subjects": [ {
+//!     "subject": "pattern.monitor.auth.>",
+//!     "exclude": ["request_id", "trace_id"],
+//!     "fields": {
+//!       "status": { "encoder": "categorical", "weight": 3 },
+//!       "latency_ms": { "encoder": "numeric", "range": "0..5000/256" },
+//!       "location": { "encoder": "text" },
+//!       "created_at": { "encoder": "timestamp", "format": "ms" }
+//!     } } ] }
+//! ```
+//!
+//! Documents are JSON. Subject patterns use NATS
+//! wildcards (`*` for one token, `>` for one or more trailing tokens) and the
+//! first matching entry applies.
+
+use crate::text::StringEncoding;
+use crate::{numeric, timestamp, EncoderConfig};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// A parsed schema document.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Schema {
+    #[serde(default)]
+    pub subjects: Vec<SubjectSchema>,
+}
+
+/// Field rules for subjects matching `subject`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SubjectSchema {
+    /// Subject pattern with NATS wildcards.
+    pub subject: String,
+    /// Field path patterns to keep; all fields if empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Field path patterns to drop, applied after `include`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Encoder options by field path.
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldSchema>,
+}
+
+/// Encoder options for one field.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FieldSchema {
+    pub encoder: Option<FieldEncoder>,
+    /// Numeric range as `min..max[/resolution][@log]`.
+    pub range: Option<String>,
+    /// Timestamp format: `rfc3339`, `s` or `ms`.
+    pub format: Option<String>,
+    /// Weight in the master bundle (default 1; 0 keeps the field out of
+    /// the bundle while still storing its vector).
+    pub weight: Option<f64>,
+}
+
+/// Encoder selected for a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FieldEncoder {
+    /// Similarity-preserving levels (see [`numeric`]).
+    Numeric,
+    /// Word and n-gram symbols (see [`crate::text`]).
+    Text,
+    /// Exact values: only identical values are similar, numbers included.
+    Categorical,
+    /// Cyclic time features (see [`timestamp`]).
+    Timestamp,
+}
+
+impl Schema {
+    /// Parse a JSON schema document.
+    pub(crate) fn parse(document: &str) -> Result<Self, String> {
+        serde_json::from_str(document).map_err(|e| format!("schema parse error: {e}"))
+    }
+
+    /// First entry whose pattern matches `subject`.
+    pub(crate) fn for_subject(&self, subject: &str) -> Option<&SubjectSchema> {
+        self.subjects
+            .iter()
+            .find(|entry| subject_matches(&entry.subject, subject))
+    }
+}
+
+impl SubjectSchema {
+    /// `base` with this entry's field filters and encoders applied.
+    pub(crate) fn apply(&self, base: &EncoderConfig) -> Result<EncoderConfig, String> {
+        let mut encoder = base.clone();
+        encoder.include.extend(self.include.iter().cloned());
+        encoder.exclude.extend(self.exclude.iter().cloned());
+        let mut weights = Vec::new();
+        for (path, field) in &self.fields {
+            let invalid = |what: &str| format!("schema field '{path}': {what}");
+            if let Some(weight) = field.weight {
+                if !weight.is_finite() || weight < 0.0 {
+                    return Err(invalid("invalid weight"));
+                }
+                weights.push((path.clone(), weight));
+            }
+            match field.encoder {
+                Some(FieldEncoder::Numeric) => {
+                    let range = match &field.range {
+                        Some(range) => numeric::parse_numeric_ranges(&format!("{path}={range}"))?
+                            .remove(path)
+                            .ok_or_else(|| invalid("invalid range"))?,
+                        None => Default::default(),
+                    };
+                    encoder.numeric_ranges.insert(path.clone(), range);
+                }
+                Some(FieldEncoder::Text) => {
+                    encoder
+                        .string_encodings
+                        .insert(path.clone(), StringEncoding::Text);
+                }
+                Some(FieldEncoder::Categorical) => {
+                    encoder
+                        .string_encodings
+                        .insert(path.clone(), StringEncoding::Exact);
+                    encoder.categorical_fields.insert(path.clone());
+                }
+                Some(FieldEncoder::Timestamp) => {
+                    let format = field.format.as_deref().unwrap_or("rfc3339");
+                    let format = timestamp::parse_timestamp_fields(&format!("{path}={format}"))?
+                        .remove(path)
+                        .ok_or_else(|| invalid("invalid format"))?;
+                    encoder.timestamp_fields.insert(path.clone(), format);
+                }
+                None => {}
+            }
+        }
+        // Schema weights take precedence over configured ones.
+        encoder.field_weights.splice(0..0, weights);
+        Ok(encoder)
+    }
+}
+
+/// Whether NATS-style `pattern` matches `subject`.
+pub(crate) fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut tokens = subject.split('.');
+    for part in pattern.split('.') {
+        match (part, tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (part, Some(token)) if part == token => {}
+            _ => return false,
+        }
+    }
+    tokens.next().is_none()
+}
+
+/// Whether a field path pattern selects `path`. `*` matches any run of
+/// characters, and a pattern naming a container also selects everything
+/// beneath it (`geo` selects `geo.lat` and `items` selects `items[0].sku`).
+///
+/// Paths come from message bodies, so matching reads `path` once, tracking
+/// which pattern prefixes match the path read so far: time is linear in
+/// `pattern.len() * path.len()` however many `*` the pattern has.
+pub(crate) fn path_matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let end = pattern.len();
+    // `states[j]`: the first `j` pattern bytes match the path read so far.
+    let mut states = vec![false; end + 1];
+    let mut next = vec![false; end + 1];
+    states[0] = true;
+    skip_stars(pattern, &mut states);
+    for &c in path.as_bytes() {
+        // The path read so far names a container of `path`.
+        if matches!(c, b'.' | b'[') && states[end] {
+            return true;
+        }
+        next.fill(false);
+        for j in (0..end).filter(|&j| states[j]) {
+            match pattern[j] {
+                b'*' => next[j] = true,
+                p if p == c => next[j + 1] = true,
+                _ => {}
+            }
+        }
+        skip_stars(pattern, &mut next);
+        if !next.contains(&true) {
+            return false;
+        }
+        std::mem::swap(&mut states, &mut next);
+    }
+    states[end]
+}
+
+/// Extend `states` across `*`s, which may match nothing.
+fn skip_stars(pattern: &[u8], states: &mut [bool]) {
+    for j in 0..pattern.len() {
+        if states[j] && pattern[j] == b'*' {
+            states[j + 1] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::TimestampFormat;
+
+    const DOCUMENT: &str = r#"{ "subjects": [
+  { "subject": "pattern.monitor.auth.>",
+    "exclude": ["request_id", "trace_id"],
+    "fields": {
+      "status": { "encoder": "categorical", "weight": 3 },
+      "latency_ms": { "encoder": "numeric", "range": "0..5000/256" },
+      "location": { "encoder": "text" },
+      "created_at": { "encoder": "timestamp", "format": "ms" } } },
+  { "subject": "pattern.monitor.*", "include": ["event", "geo"] }
+] }"#;
+
+    #[test]
+    fn test_subject_wildcards() {
+        assert!(subject_matches(
+            "pattern.monitor.>",
+            "pattern.monitor.auth.eu"
+        ));
+        assert!(!subject_matches("pattern.monitor.>", "pattern.monitor"));
+        assert!(subject_matches("pattern.*.auth", "pattern.monitor.auth"));
+        assert!(!subject_matches("pattern.*", "pattern.monitor.auth"));
+        assert!(subject_matches("pattern.monitor", "pattern.monitor"));
+    }
+
+    #[test]
+    fn test_path_patterns() {
+        assert!(path_matches("trace_id", "trace_id"));
+        assert!(!path_matches("trace_id", "trace_id_hash"));
+        assert!(path_matches("geo", "geo.lat"));
+        assert!(path_matches("items", "items[0].sku"));
+        assert!(path_matches("*.trace_id", "meta.trace_id"));
+        assert!(path_matches("items[*].sku", "items[3].sku"));
+        assert!(!path_matches("geo.lat", "geo"));
+        assert!(path_matches("*", "anything.at[0].all"));
+        assert!(path_matches("**id", "trace_id"));
+        assert!(path_matches("a*b*c", "a.xb.c"));
+        assert!(!path_matches("a*b*c", "a.xb.d"));
+        assert!(!path_matches("", "geo"));
+    }
+
+    #[test]
+    fn test_many_stars_on_a_long_path_stay_fast() {
+        let pattern = "*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let path = "a".repeat(4096);
+        assert!(!path_matches(pattern, &path));
+        assert!(path_matches(pattern, &format!("{path}b")));
+    }
+
+    #[test]
+    fn test_first_matching_subject_applies() {
+        let schema = Schema::parse(DOCUMENT).unwrap();
+        let auth = schema.for_subject("pattern.monitor.auth.login").unwrap();
+        assert_eq!(auth.exclude, ["request_id", "trace_id"]);
+        let other = schema.for_subject("pattern.monitor.billing").unwrap();
+        assert_eq!(other.include, ["event", "geo"]);
+        assert!(schema.for_subject("other.stream").is_none());
+    }
+
+    #[test]
+    fn test_apply_selects_encoders() {
+        let schema = Schema::parse(DOCUMENT).unwrap();
+        let encoder = schema
+            .for_subject("pattern.monitor.auth.login")
+            .unwrap()
+            .apply(&EncoderConfig::default())
+            .unwrap();
+        assert!(!encoder.selects("trace_id"));
+        assert!(encoder.selects("status"));
+        assert!(encoder.categorical_fields.contains("status"));
+        assert_eq!(encoder.field_weights, [("status".to_string(), 3.0)]);
+        assert_eq!(encoder.numeric_ranges["latency_ms"].max, 5000.0);
+        assert_eq!(encoder.string_encodings["location"], StringEncoding::Text);
+        assert_eq!(
+            encoder.timestamp_fields["created_at"],
+            TimestampFormat::EpochMillis
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let schema = Schema::parse(r#"{"subjects":[{"subject":"a.>","exclude":["id"]}]}"#).unwrap();
+        assert_eq!(schema.subjects[0].exclude, ["id"]);
+        assert!(Schema::parse("subjects: [{subject: a.>, exclude: [id]}]").is_err());
+        assert!(Schema::parse(
+            r#"{"subjects":[{"subject":"a","fields":{"x":{"encoder":"fuzzy"}}}]}"#
+        )
+        .is_err());
+        let bad_range = Schema::parse(
+            r#"{"subjects":[{"subject":"a","fields":{"x":{"encoder":"numeric","range":"9..1"}}}]}"#,
+        )
+        .unwrap();
+        assert!(bad_range.subjects[0]
+            .apply(&EncoderConfig::default())
+            .is_err());
+    }
+}
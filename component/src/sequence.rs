@@ -0,0 +1,68 @@
+//! Order-aware and order-insensitive encoding of element sequences.
+//!
+//! A sequence is the bundle of its element vectors, each cyclically permuted
+//! by its position, so shared elements at shared positions keep sequences
+//! similar (`[1,2,3]` vs `[1,2,3,4]`) while reorderings diverge (`[1,2,3]` vs
+//! `[3,2,1]`). A set bundles the elements unpermuted, so order is ignored.
+
+use embeddenator_vsa::SparseVec;
+
+/// Permutation step between consecutive positions. A prime well above the
+/// 256-index byte range of `encode_data`, so a shifted element never aliases
+/// another byte of an unshifted one, and coprime with `DIM` so positions
+/// below `DIM` never coincide.
+pub(crate) const POSITION_SHIFT: usize = 1013;
+
+/// Permute `item` into position `position` of a sequence.
+pub(crate) fn at_position(item: &SparseVec, position: usize) -> SparseVec {
+    item.permute(position.wrapping_mul(POSITION_SHIFT))
+}
+
+/// Order-sensitive encoding: bundle of position-permuted items.
+pub(crate) fn encode_sequence(items: &[SparseVec]) -> SparseVec {
+    let permuted: Vec<SparseVec> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| at_position(item, i))
+        .collect();
+    SparseVec::bundle_sum_many(&permuted)
+}
+
+/// Order-insensitive encoding: plain bundle of the items.
+pub(crate) fn encode_set(items: &[SparseVec]) -> SparseVec {
+    SparseVec::bundle_sum_many(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_value;
+    use serde_json::json;
+
+    fn items(values: &[i64]) -> Vec<SparseVec> {
+        values.iter().map(|v| encode_value(&json!(v))).collect()
+    }
+
+    #[test]
+    fn test_sequence_prefix_stays_similar() {
+        let short = encode_sequence(&items(&[10, 20, 30]));
+        let longer = encode_sequence(&items(&[10, 20, 30, 40]));
+        assert!(short.cosine(&longer) > 0.7, "got {}", short.cosine(&longer));
+    }
+
+    #[test]
+    fn test_sequence_is_order_sensitive() {
+        let forward = encode_sequence(&items(&[10, 20, 30]));
+        let reversed = encode_sequence(&items(&[30, 20, 10]));
+        let prefix = encode_sequence(&items(&[10, 20, 30, 40]));
+        assert!(forward.cosine(&reversed) < forward.cosine(&prefix));
+        assert!(forward.cosine(&reversed) < 0.9);
+    }
+
+    #[test]
+    fn test_set_ignores_order() {
+        let forward = encode_set(&items(&[10, 20, 30]));
+        let reversed = encode_set(&items(&[30, 20, 10]));
+        assert!((forward.cosine(&reversed) - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,478 @@
+//! Sequence patterns across consecutive messages on a subject.
+//!
+//! Many incidents are a sequence (`login_failed` three times, then
+//! `login_success`) rather than one odd message. Each subject keeps its last
+//! `sequence_length` message bundles and encodes them as one order-aware
+//! hypervector (see [`crate::sequence::encode_sequence`]), oldest first.
+//! The store, kept under `sequences:v1:{subject}` as bincode, holds two kinds
+//! of known patterns:
+//!
+//! - learned patterns: every sequence that matched no known pattern, up to
+//!   `sequence_limit` of them (the least seen makes room for a new one);
+//! - registered patterns: named example sequences posted over HTTP, marked
+//!   suspicious (alert when seen) or benign (never alert as unseen), up to
+//!   [`MAX_REGISTERED_PATTERNS`] of them. They may be shorter than
+//!   `sequence_length`, but not longer, and are matched against the latest
+//!   messages.
+//!
+//! Once `sequence_warmup` sequences have been observed, a sequence matching
+//! no known pattern by at least `sequence_threshold` is reported as unseen.
+
+use crate::sequence::encode_sequence;
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Default number of consecutive messages in a sequence.
+pub(crate) const DEFAULT_SEQUENCE_LENGTH: usize = 4;
+
+/// Default number of learned patterns kept per subject.
+pub(crate) const DEFAULT_SEQUENCE_LIMIT: usize = 64;
+
+/// Default similarity at or above which a sequence matches a pattern.
+pub(crate) const DEFAULT_SEQUENCE_THRESHOLD: f64 = 0.75;
+
+/// Default number of sequences observed before unseen ones are reported.
+pub(crate) const DEFAULT_SEQUENCE_WARMUP: u64 = 32;
+
+/// Largest number of registered patterns kept per subject. Each is matched
+/// against every message, so their number is bounded like learned ones.
+pub(crate) const MAX_REGISTERED_PATTERNS: usize = 64;
+
+/// A known sequence pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SequencePattern {
+    /// Name of a registered pattern; `None` for learned ones.
+    pub name: Option<String>,
+    /// Whether seeing the pattern raises an alert.
+    pub suspicious: bool,
+    /// Number of messages in the pattern.
+    pub length: usize,
+    /// Times the pattern was seen.
+    pub count: u64,
+    pub vector: SparseVec,
+}
+
+/// A known pattern as listed over HTTP, without its vector.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct PatternSummary {
+    pub name: Option<String>,
+    pub suspicious: bool,
+    pub length: usize,
+    pub count: u64,
+}
+
+/// Why a sequence was reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FindingKind {
+    /// No known pattern matched.
+    Unseen,
+    /// A registered suspicious pattern matched.
+    Suspicious,
+}
+
+/// A reported sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SequenceFinding {
+    pub kind: FindingKind,
+    /// Name of the matched pattern, for suspicious sequences.
+    pub pattern: Option<String>,
+    /// Similarity to the matched pattern, or to the closest known one for
+    /// unseen sequences (0 if none is known).
+    pub similarity: f64,
+    /// Number of messages in the reported sequence.
+    pub length: usize,
+}
+
+/// Outcome of observing one message.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct SequenceObservation {
+    /// Similarity of the latest `sequence_length` messages to the closest
+    /// known pattern; `None` until that many messages were seen.
+    pub similarity: Option<f64>,
+    pub findings: Vec<SequenceFinding>,
+}
+
+/// Recent messages and known patterns of one subject.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct SequenceStore {
+    /// Latest message bundles, oldest first.
+    pub recent: Vec<SparseVec>,
+    /// Full-length sequences observed so far.
+    pub observed: u64,
+    pub patterns: Vec<SequencePattern>,
+}
+
+impl SequenceStore {
+    /// Parse a store read from the bucket.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes).map_err(|e| format!("sequence store decode error: {e}"))
+    }
+
+    /// Serialise the store for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("sequence store encode error: {e}"))
+    }
+
+    /// Register the sequence of `messages` (oldest first) under `name`,
+    /// replacing a pattern registered under the same name. The sequence may
+    /// hold at most `max_length` messages, as every message keeps that many
+    /// recent bundles for matching.
+    pub(crate) fn register(
+        &mut self,
+        name: &str,
+        messages: &[SparseVec],
+        suspicious: bool,
+        max_length: usize,
+    ) -> Result<(), String> {
+        if messages.is_empty() {
+            return Err(format!("sequence '{name}' has no messages"));
+        }
+        if messages.len() > max_length {
+            return Err(format!(
+                "sequence '{name}' has {} messages, more than sequence_length ({max_length})",
+                messages.len()
+            ));
+        }
+        self.patterns.retain(|p| p.name.as_deref() != Some(name));
+        let registered = self.patterns.iter().filter(|p| p.name.is_some()).count();
+        if registered >= MAX_REGISTERED_PATTERNS {
+            return Err(format!(
+                "subject already has {MAX_REGISTERED_PATTERNS} registered sequences"
+            ));
+        }
+        self.patterns.push(SequencePattern {
+            name: Some(name.to_string()),
+            suspicious,
+            length: messages.len(),
+            count: 0,
+            vector: encode_sequence(messages),
+        });
+        Ok(())
+    }
+
+    /// Append `message` and match the latest messages against the known
+    /// patterns (see the module docs).
+    pub(crate) fn observe(
+        &mut self,
+        message: &SparseVec,
+        length: usize,
+        limit: usize,
+        threshold: f64,
+        warmup: u64,
+    ) -> SequenceObservation {
+        let longest = self
+            .patterns
+            .iter()
+            .map(|p| p.length)
+            .fold(length, usize::max);
+        self.recent.push(message.clone());
+        let excess = self.recent.len().saturating_sub(longest);
+        self.recent.drain(..excess);
+
+        let mut observation = SequenceObservation::default();
+        let mut registered_match = false;
+        for pattern in self.patterns.iter_mut().filter(|p| p.name.is_some()) {
+            let Some(latest) = latest(&self.recent, pattern.length) else {
+                continue;
+            };
+            let similarity = encode_sequence(latest).cosine(&pattern.vector);
+            if similarity < threshold {
+                continue;
+            }
+            pattern.count += 1;
+            registered_match = true;
+            if pattern.suspicious {
+                observation.findings.push(SequenceFinding {
+                    kind: FindingKind::Suspicious,
+                    pattern: pattern.name.clone(),
+                    similarity,
+                    length: pattern.length,
+                });
+            }
+        }
+
+        let Some(latest) = latest(&self.recent, length) else {
+            return observation;
+        };
+        let sequence = encode_sequence(latest);
+        self.observed += 1;
+        let closest = self
+            .patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.length == length)
+            .map(|(i, p)| (i, p.vector.cosine(&sequence)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        let similarity = closest.map(|(_, sim)| sim).unwrap_or(0.0);
+        observation.similarity = Some(similarity);
+        match closest {
+            Some((i, sim)) if sim >= threshold => {
+                // Registered matches were counted above.
+                if self.patterns[i].name.is_none() {
+                    self.patterns[i].count += 1;
+                }
+            }
+            _ => {
+                if !registered_match && self.observed > warmup {
+                    observation.findings.push(SequenceFinding {
+                        kind: FindingKind::Unseen,
+                        pattern: None,
+                        similarity,
+                        length,
+                    });
+                }
+                self.learn(sequence, length, limit);
+            }
+        }
+        observation
+    }
+
+    /// Known patterns, registered ones first, then by count.
+    pub(crate) fn summaries(&self) -> Vec<PatternSummary> {
+        let mut summaries: Vec<PatternSummary> = self
+            .patterns
+            .iter()
+            .map(|p| PatternSummary {
+                name: p.name.clone(),
+                suspicious: p.suspicious,
+                length: p.length,
+                count: p.count,
+            })
+            .collect();
+        summaries.sort_by(|a, b| {
+            b.name
+                .is_some()
+                .cmp(&a.name.is_some())
+                .then_with(|| b.count.cmp(&a.count))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        summaries
+    }
+
+    /// Remember a new learned pattern, making room by dropping the least
+    /// seen learned one (the oldest among equals).
+    fn learn(&mut self, vector: SparseVec, length: usize, limit: usize) {
+        if limit == 0 {
+            return;
+        }
+        let learned = self.patterns.iter().filter(|p| p.name.is_none()).count();
+        if learned >= limit {
+            if let Some(rarest) = self
+                .patterns
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| p.name.is_none())
+                .min_by_key(|(_, p)| p.count)
+                .map(|(i, _)| i)
+            {
+                self.patterns.remove(rarest);
+            }
+        }
+        self.patterns.push(SequencePattern {
+            name: None,
+            suspicious: false,
+            length,
+            count: 1,
+            vector,
+        });
+    }
+}
+
+/// The last `length` of `recent`, if there are that many.
+fn latest(recent: &[SparseVec], length: usize) -> Option<&[SparseVec]> {
+    let start = recent.len().checked_sub(length)?;
+    Some(&recent[start..])
+}
+
+/// A request to register a named sequence pattern.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SequenceRegistration {
+    pub name: String,
+    /// Example messages, oldest first, encoded like incoming ones.
+    pub messages: Vec<Map<String, Value>>,
+    /// Whether the pattern raises an alert; benign patterns only silence
+    /// unseen-sequence alerts.
+    #[serde(default = "default_suspicious")]
+    pub suspicious: bool,
+}
+
+fn default_suspicious() -> bool {
+    true
+}
+
+impl SequenceRegistration {
+    /// Parse a request body.
+    pub(crate) fn parse(body: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(body).map_err(|e| format!("sequence registration parse error: {e}"))
+    }
+
+    /// The example messages re-serialised as message bodies.
+    pub(crate) fn message_bodies(&self) -> Vec<Vec<u8>> {
+        self.messages
+            .iter()
+            .map(|m| Value::Object(m.clone()).to_string().into_bytes())
+            .collect()
+    }
+}
+
+/// JSON payload published on the alert subject for a reported sequence.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "sequence")]
+pub(crate) struct SequenceAlert {
+    pub subject: String,
+    pub kind: FindingKind,
+    /// Name of the matched registered pattern.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub similarity: f64,
+    /// Number of messages in the sequence.
+    pub length: usize,
+    /// Unix epoch milliseconds at which the sequence completed.
+    pub timestamp: u64,
+}
+
+impl SequenceAlert {
+    /// Alert for `finding` on `subject`.
+    pub(crate) fn new(subject: &str, finding: &SequenceFinding, timestamp: u64) -> Self {
+        Self {
+            subject: subject.to_string(),
+            kind: finding.kind,
+            pattern: finding.pattern.clone(),
+            similarity: finding.similarity,
+            length: finding.length,
+            timestamp,
+        }
+    }
+
+    /// Serialise the alert as the published message body.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("sequence alert encode error: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::sparse_code;
+
+    fn event(name: &str) -> SparseVec {
+        sparse_code(name, 200)
+    }
+
+    fn observe(store: &mut SequenceStore, name: &str, warmup: u64) -> SequenceObservation {
+        store.observe(&event(name), 3, 8, 0.75, warmup)
+    }
+
+    #[test]
+    fn test_repeated_sequence_is_learned() {
+        let mut store = SequenceStore::default();
+        for name in ["a", "b", "c", "a", "b", "c", "a", "b", "c"] {
+            observe(&mut store, name, 100);
+        }
+        assert_eq!(store.recent.len(), 3);
+        // abc, bca and cab, each seen three or two times.
+        assert_eq!(store.patterns.len(), 3);
+        let last = observe(&mut store, "a", 0);
+        assert!(last.findings.is_empty(), "got {:?}", last.findings);
+        assert!((last.similarity.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unseen_sequence_is_reported_after_warmup() {
+        let mut store = SequenceStore::default();
+        assert!(observe(&mut store, "a", 0).similarity.is_none());
+        observe(&mut store, "b", 0);
+        // The first full sequence is unseen, but still within the warm-up.
+        assert!(observe(&mut store, "c", 1).findings.is_empty());
+        let unseen = observe(&mut store, "x", 1);
+        assert_eq!(unseen.findings.len(), 1);
+        assert_eq!(unseen.findings[0].kind, FindingKind::Unseen);
+        assert!(unseen.findings[0].similarity < 0.75);
+    }
+
+    #[test]
+    fn test_registered_suspicious_sequence_alerts() {
+        let mut store = SequenceStore::default();
+        let failed = event("login_failed");
+        let attack = [failed.clone(), failed.clone(), event("login_success")];
+        store.register("brute-force", &attack, true, 3).unwrap();
+        store
+            .register("routine", &[event("login_success")], false, 3)
+            .unwrap();
+        assert!(store.register("empty", &[], true, 3).is_err());
+        let too_long = [failed.clone(), failed.clone(), failed.clone(), event("x")];
+        assert!(store.register("too-long", &too_long, true, 3).is_err());
+
+        observe(&mut store, "login_failed", 100);
+        observe(&mut store, "login_failed", 100);
+        let seen = observe(&mut store, "login_success", 100);
+        assert_eq!(seen.findings.len(), 1);
+        assert_eq!(seen.findings[0].kind, FindingKind::Suspicious);
+        assert_eq!(seen.findings[0].pattern.as_deref(), Some("brute-force"));
+        assert_eq!(seen.findings[0].length, 3);
+
+        let summaries = store.summaries();
+        assert_eq!(summaries[0].name.as_deref(), Some("brute-force"));
+        assert_eq!(summaries[0].count, 1);
+        assert_eq!(summaries[1].name.as_deref(), Some("routine"));
+
+        let restored = SequenceStore::from_bytes(&store.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.summaries(), summaries);
+    }
+
+    #[test]
+    fn test_benign_pattern_silences_unseen_alert() {
+        let mut store = SequenceStore::default();
+        store
+            .register("deploy", &[event("deploy")], false, 3)
+            .unwrap();
+        observe(&mut store, "x", 0);
+        observe(&mut store, "y", 0);
+        let benign = observe(&mut store, "deploy", 0);
+        assert!(benign.findings.is_empty(), "got {:?}", benign.findings);
+    }
+
+    #[test]
+    fn test_registered_patterns_are_capped() {
+        let mut store = SequenceStore::default();
+        for n in 0..MAX_REGISTERED_PATTERNS {
+            let name = format!("p{n}");
+            store.register(&name, &[event(&name)], true, 3).unwrap();
+        }
+        assert!(store.register("one-more", &[event("x")], true, 3).is_err());
+        // Replacing a registered pattern is still allowed at the cap.
+        store.register("p0", &[event("y")], false, 3).unwrap();
+        assert_eq!(store.patterns.len(), MAX_REGISTERED_PATTERNS);
+    }
+
+    #[test]
+    fn test_registration_and_alert_json() {
+        let registration = SequenceRegistration::parse(
+            br#"{"name":"brute-force","messages":[{"event":"login_failed"},{"event":"login_success"}]}"#,
+        )
+        .unwrap();
+        assert!(registration.suspicious);
+        assert_eq!(
+            registration.message_bodies()[1],
+            br#"{"event":"login_success"}"#
+        );
+        assert!(SequenceRegistration::parse(br#"{"name":"x"}"#).is_err());
+
+        let finding = SequenceFinding {
+            kind: FindingKind::Suspicious,
+            pattern: Some("brute-force".to_string()),
+            similarity: 0.9,
+            length: 2,
+        };
+        let json: serde_json::Value =
+            serde_json::from_slice(&SequenceAlert::new("auth", &finding, 5).to_json().unwrap())
+                .unwrap();
+        assert_eq!(json["type"], "sequence");
+        assert_eq!(json["kind"], "suspicious");
+        assert_eq!(json["pattern"], "brute-force");
+    }
+}
@@ -0,0 +1,414 @@
+//! Runtime settings read from `wasi:config/runtime`.
+//!
+//! Every setting has a default, so a deployment without any config behaves
+//! like the original build. Values are strings in the component config of
+//! `wadm.yaml`; keys this component does not know are ignored so the same
+//! config can carry settings for other components.
+
+use crate::codebook::DEFAULT_CODEBOOK_LIMIT;
+use crate::decay::MAX_HALF_LIFE;
+use crate::drift::{DEFAULT_DRIFT_SUBJECT, DEFAULT_DRIFT_THRESHOLD};
+use crate::history::DEFAULT_HISTORY_LIMIT;
+use crate::keys::KeySchema;
+use crate::prototype::{DEFAULT_MERGE_THRESHOLD, DEFAULT_PROTOTYPE_LIMIT, DEFAULT_SPAWN_THRESHOLD};
+use crate::query::DEFAULT_QUERY_SUBJECT;
+use crate::schema::Schema;
+use crate::sequence_patterns::{
+    DEFAULT_SEQUENCE_LENGTH, DEFAULT_SEQUENCE_LIMIT, DEFAULT_SEQUENCE_THRESHOLD,
+    DEFAULT_SEQUENCE_WARMUP,
+};
+use crate::window::{WindowSize, DEFAULT_WINDOW_RETENTION};
+use crate::{numeric, text, timestamp, weights, ArrayMode, EncoderConfig, StructureMode};
+
+/// Default bucket holding vectors, bundles and metadata.
+pub(crate) const DEFAULT_BUCKET: &str = "pattern-monitor-vectors";
+
+/// Default subject anomaly alerts are published to.
+pub(crate) const DEFAULT_ALERT_SUBJECT: &str = "pattern.alerts";
+
+/// Default novelty score at or above which an alert is published.
+pub(crate) const DEFAULT_NOVELTY_THRESHOLD: f64 = 0.6;
+
+/// Default number of most-deviating fields included in an alert.
+pub(crate) const DEFAULT_ALERT_TOP_FIELDS: usize = 3;
+
+/// Settings for one deployment of the component.
+#[derive(Debug, Clone)]
+pub(crate) struct Settings {
+    /// Key-value bucket (`bucket`).
+    pub bucket: String,
+    /// Key prefixes (`key_prefix_semantic`, `key_prefix_bundle`,
+    /// `key_prefix_bundle_votes`, `key_prefix_bundle_meta`,
+    /// `key_prefix_field_ids`, `key_prefix_history`, `key_prefix_codebook`,
+    /// `key_prefix_window`, `key_prefix_window_index`, `key_prefix_decay`,
+    /// `key_prefix_drift`, `key_prefix_prototypes`, `key_prefix_sequences`),
+    /// subject registry key (`key_subjects`) and tenant (`tenant`).
+    pub keys: KeySchema,
+    /// Alert subject (`alert_subject`). Must not match the subscription or
+    /// alerts would be fed back in.
+    pub alert_subject: String,
+    /// Subject pattern similarity queries are received on
+    /// (`query_subject`). Messages matching it are answered, not encoded.
+    pub query_subject: String,
+    /// Alert threshold (`novelty_threshold`).
+    pub novelty_threshold: f64,
+    /// Fields listed in an alert (`alert_top_fields`).
+    pub alert_top_fields: usize,
+    /// Messages kept in each subject's searchable history
+    /// (`history_limit`); 0 disables the history.
+    pub history_limit: usize,
+    /// Distinct values remembered per field for value recall
+    /// (`codebook_limit`); 0 disables the codebook.
+    pub codebook_limit: usize,
+    /// Length of the time windows bundled per subject (`window_size`).
+    pub window_size: WindowSize,
+    /// Windows kept per subject (`window_retention`); 0 disables windowed
+    /// bundles.
+    pub window_retention: usize,
+    /// Half-life in messages of the decaying baseline messages are scored
+    /// against (`decay_half_life`); 0 scores against the master bundle.
+    pub decay_half_life: u32,
+    /// Drift event subject (`drift_subject`). Like `alert_subject`, it must
+    /// not match the subscription.
+    pub drift_subject: String,
+    /// Drift magnitude between successive windows at or above which a drift
+    /// event is published (`drift_threshold`).
+    pub drift_threshold: f64,
+    /// Pattern prototypes kept per subject (`prototype_limit`); 0 disables
+    /// clustering.
+    pub prototype_limit: usize,
+    /// Similarity to the closest prototype below which a message spawns a
+    /// new one (`prototype_spawn_threshold`).
+    pub prototype_spawn_threshold: f64,
+    /// Similarity at or above which two prototypes are merged
+    /// (`prototype_merge_threshold`).
+    pub prototype_merge_threshold: f64,
+    /// Consecutive messages encoded as one sequence (`sequence_length`); 0
+    /// disables sequence patterns.
+    pub sequence_length: usize,
+    /// Learned sequence patterns kept per subject (`sequence_limit`).
+    pub sequence_limit: usize,
+    /// Similarity at or above which a sequence matches a known pattern
+    /// (`sequence_threshold`).
+    pub sequence_threshold: f64,
+    /// Sequences observed per subject before unseen ones are alerted on
+    /// (`sequence_warmup`).
+    pub sequence_warmup: u64,
+    /// Encoder options (`encoder_*`).
+    pub encoder: EncoderConfig,
+    /// Inline field schema document (`schema`).
+    pub schema: Option<Schema>,
+    /// Bucket key holding a field schema document (`schema_key`), re-read
+    /// periodically and used instead of `schema` when present.
+    pub schema_key: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bucket: DEFAULT_BUCKET.to_string(),
+            keys: KeySchema::default(),
+            alert_subject: DEFAULT_ALERT_SUBJECT.to_string(),
+            query_subject: DEFAULT_QUERY_SUBJECT.to_string(),
+            novelty_threshold: DEFAULT_NOVELTY_THRESHOLD,
+            alert_top_fields: DEFAULT_ALERT_TOP_FIELDS,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            codebook_limit: DEFAULT_CODEBOOK_LIMIT,
+            window_size: WindowSize::default(),
+            window_retention: DEFAULT_WINDOW_RETENTION,
+            decay_half_life: 0,
+            drift_subject: DEFAULT_DRIFT_SUBJECT.to_string(),
+            drift_threshold: DEFAULT_DRIFT_THRESHOLD,
+            prototype_limit: DEFAULT_PROTOTYPE_LIMIT,
+            prototype_spawn_threshold: DEFAULT_SPAWN_THRESHOLD,
+            prototype_merge_threshold: DEFAULT_MERGE_THRESHOLD,
+            sequence_length: DEFAULT_SEQUENCE_LENGTH,
+            sequence_limit: DEFAULT_SEQUENCE_LIMIT,
+            sequence_threshold: DEFAULT_SEQUENCE_THRESHOLD,
+            sequence_warmup: DEFAULT_SEQUENCE_WARMUP,
+            encoder: EncoderConfig::default(),
+            schema: None,
+            schema_key: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Encoder for `subject`: the configured encoder with the first
+    /// matching entry of `schema` applied, if any.
+    pub(crate) fn encoder_for(
+        &self,
+        schema: Option<&Schema>,
+        subject: &str,
+    ) -> Result<EncoderConfig, String> {
+        match schema.and_then(|schema| schema.for_subject(subject)) {
+            Some(entry) => entry.apply(&self.encoder),
+            None => Ok(self.encoder.clone()),
+        }
+    }
+
+    /// Build settings from config key/value pairs, starting from the
+    /// defaults. Returns `Err` for a known key with an invalid value.
+    pub(crate) fn from_pairs<I>(pairs: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut settings = Self::default();
+        for (key, value) in pairs {
+            let invalid = || format!("invalid config value for '{key}': '{value}'");
+            let raw = value.trim();
+            match key.as_str() {
+                "bucket" => settings.bucket = non_empty(raw).ok_or_else(invalid)?,
+                "tenant" => settings.keys.tenant = non_empty(raw).ok_or_else(invalid)?,
+                "key_prefix_semantic" => {
+                    settings.keys.semantic = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_bundle" => settings.keys.bundle = non_empty(raw).ok_or_else(invalid)?,
+                "key_subjects" => settings.keys.subjects = non_empty(raw).ok_or_else(invalid)?,
+                "key_prefix_bundle_votes" => {
+                    settings.keys.bundle_votes = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_bundle_meta" => {
+                    settings.keys.bundle_meta = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_field_ids" => {
+                    settings.keys.field_ids = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_history" => {
+                    settings.keys.history = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_codebook" => {
+                    settings.keys.codebook = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_window" => settings.keys.window = non_empty(raw).ok_or_else(invalid)?,
+                "key_prefix_window_index" => {
+                    settings.keys.window_index = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_decay" => settings.keys.decay = non_empty(raw).ok_or_else(invalid)?,
+                "key_prefix_drift" => settings.keys.drift = non_empty(raw).ok_or_else(invalid)?,
+                "key_prefix_prototypes" => {
+                    settings.keys.prototypes = non_empty(raw).ok_or_else(invalid)?
+                }
+                "key_prefix_sequences" => {
+                    settings.keys.sequences = non_empty(raw).ok_or_else(invalid)?
+                }
+                "alert_subject" => settings.alert_subject = non_empty(raw).ok_or_else(invalid)?,
+                "query_subject" => settings.query_subject = non_empty(raw).ok_or_else(invalid)?,
+                "novelty_threshold" => {
+                    settings.novelty_threshold = unit_interval(raw).ok_or_else(invalid)?
+                }
+                "alert_top_fields" => {
+                    settings.alert_top_fields = raw.parse().map_err(|_| invalid())?
+                }
+                "history_limit" => settings.history_limit = raw.parse().map_err(|_| invalid())?,
+                "codebook_limit" => settings.codebook_limit = raw.parse().map_err(|_| invalid())?,
+                "window_size" => {
+                    settings.window_size = WindowSize::parse(raw).ok_or_else(invalid)?
+                }
+                "window_retention" => {
+                    settings.window_retention = raw.parse().map_err(|_| invalid())?
+                }
+                "decay_half_life" => {
+                    settings.decay_half_life = raw
+                        .parse()
+                        .ok()
+                        .filter(|h| *h <= MAX_HALF_LIFE)
+                        .ok_or_else(invalid)?
+                }
+                "drift_subject" => settings.drift_subject = non_empty(raw).ok_or_else(invalid)?,
+                "drift_threshold" => {
+                    settings.drift_threshold = unit_interval(raw).ok_or_else(invalid)?
+                }
+                "prototype_limit" => {
+                    settings.prototype_limit = raw.parse().map_err(|_| invalid())?
+                }
+                "prototype_spawn_threshold" => {
+                    settings.prototype_spawn_threshold = unit_interval(raw).ok_or_else(invalid)?
+                }
+                "prototype_merge_threshold" => {
+                    settings.prototype_merge_threshold = unit_interval(raw).ok_or_else(invalid)?
+                }
+                "sequence_length" => {
+                    settings.sequence_length = raw.parse().map_err(|_| invalid())?
+                }
+                "sequence_limit" => settings.sequence_limit = raw.parse().map_err(|_| invalid())?,
+                "sequence_threshold" => {
+                    settings.sequence_threshold = unit_interval(raw).ok_or_else(invalid)?
+                }
+                "sequence_warmup" => {
+                    settings.sequence_warmup = raw.parse().map_err(|_| invalid())?
+                }
+                "encoder_max_depth" => {
+                    settings.encoder.max_depth =
+                        raw.parse().ok().filter(|d| *d > 0).ok_or_else(invalid)?
+                }
+                "encoder_structure" => {
+                    settings.encoder.structure = StructureMode::parse(raw).ok_or_else(invalid)?
+                }
+                "encoder_arrays" => {
+                    settings.encoder.arrays = ArrayMode::parse(raw).ok_or_else(invalid)?
+                }
+                "encoder_numeric_ranges" => {
+                    settings.encoder.numeric_ranges = numeric::parse_numeric_ranges(raw)?
+                }
+                "encoder_string_fields" => {
+                    settings.encoder.string_encodings = text::parse_string_encodings(raw)?
+                }
+                "encoder_timestamp_fields" => {
+                    settings.encoder.timestamp_fields = timestamp::parse_timestamp_fields(raw)?
+                }
+                "encoder_detect_timestamps" => {
+                    settings.encoder.detect_timestamps = raw.parse().map_err(|_| invalid())?
+                }
+                "field_weights" => {
+                    settings.encoder.field_weights = weights::parse_field_weights(raw)?
+                }
+                "learn_weights" => {
+                    settings.encoder.learn_weights = raw.parse().map_err(|_| invalid())?
+                }
+                "schema" => settings.schema = Some(Schema::parse(raw)?),
+                "schema_key" => settings.schema_key = Some(non_empty(raw).ok_or_else(invalid)?),
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn unit_interval(value: &str) -> Option<f64> {
+    value.parse().ok().filter(|v: &f64| (0.0..=1.0).contains(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::StringEncoding;
+
+    fn settings(pairs: &[(&str, &str)]) -> Result<Settings, String> {
+        Settings::from_pairs(pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+    }
+
+    #[test]
+    fn test_defaults_without_config() {
+        let settings = settings(&[]).unwrap();
+        assert_eq!(settings.bucket, DEFAULT_BUCKET);
+        assert_eq!(settings.keys, KeySchema::default());
+        assert_eq!(settings.alert_subject, DEFAULT_ALERT_SUBJECT);
+        assert_eq!(settings.query_subject, DEFAULT_QUERY_SUBJECT);
+        assert_eq!(settings.novelty_threshold, DEFAULT_NOVELTY_THRESHOLD);
+        assert_eq!(settings.alert_top_fields, DEFAULT_ALERT_TOP_FIELDS);
+        assert_eq!(settings.history_limit, DEFAULT_HISTORY_LIMIT);
+        assert_eq!(settings.codebook_limit, DEFAULT_CODEBOOK_LIMIT);
+        assert_eq!(settings.window_size, WindowSize::Hour);
+        assert_eq!(settings.window_retention, DEFAULT_WINDOW_RETENTION);
+        assert_eq!(settings.decay_half_life, 0);
+        assert_eq!(settings.drift_subject, DEFAULT_DRIFT_SUBJECT);
+        assert_eq!(settings.drift_threshold, DEFAULT_DRIFT_THRESHOLD);
+        assert_eq!(settings.prototype_limit, DEFAULT_PROTOTYPE_LIMIT);
+        assert_eq!(settings.prototype_spawn_threshold, DEFAULT_SPAWN_THRESHOLD);
+        assert_eq!(settings.prototype_merge_threshold, DEFAULT_MERGE_THRESHOLD);
+        assert_eq!(settings.sequence_length, DEFAULT_SEQUENCE_LENGTH);
+        assert_eq!(settings.sequence_limit, DEFAULT_SEQUENCE_LIMIT);
+        assert_eq!(settings.sequence_threshold, DEFAULT_SEQUENCE_THRESHOLD);
+        assert_eq!(settings.sequence_warmup, DEFAULT_SEQUENCE_WARMUP);
+        assert_eq!(settings.encoder.structure, StructureMode::Flatten);
+    }
+
+    #[test]
+    fn test_config_overrides_defaults() {
+        let settings = settings(&[
+            ("bucket", "stream-a"),
+            ("tenant", "acme"),
+            ("key_prefix_bundle", "a:bundle"),
+            ("key_subjects", "a:subjects"),
+            ("alert_subject", "alerts.stream-a"),
+            ("novelty_threshold", "0.45"),
+            ("alert_top_fields", "5"),
+            ("history_limit", "0"),
+            ("window_size", "minute"),
+            ("window_retention", "60"),
+            ("decay_half_life", "500"),
+            ("drift_threshold", "0.25"),
+            ("prototype_limit", "4"),
+            ("prototype_spawn_threshold", "0.3"),
+            ("sequence_length", "0"),
+            ("encoder_structure", "hierarchical"),
+            ("encoder_arrays", "set"),
+            ("encoder_string_fields", "location=text"),
+            ("encoder_detect_timestamps", "true"),
+            ("field_weights", "status=3"),
+            ("learn_weights", "true"),
+            ("unrelated", "ignored"),
+        ])
+        .unwrap();
+        assert_eq!(settings.bucket, "stream-a");
+        assert_eq!(settings.keys.semantic_key("s", "f"), "semantic:v2:acme:s:f");
+        assert_eq!(settings.keys.bundle_key("s"), "a:bundle:s");
+        assert_eq!(settings.keys.subjects, "a:subjects");
+        assert_eq!(settings.alert_subject, "alerts.stream-a");
+        assert_eq!(settings.novelty_threshold, 0.45);
+        assert_eq!(settings.alert_top_fields, 5);
+        assert_eq!(settings.history_limit, 0);
+        assert_eq!(settings.window_size, WindowSize::Minute);
+        assert_eq!(settings.window_retention, 60);
+        assert_eq!(settings.decay_half_life, 500);
+        assert_eq!(settings.drift_threshold, 0.25);
+        assert_eq!(settings.prototype_limit, 4);
+        assert_eq!(settings.prototype_spawn_threshold, 0.3);
+        assert_eq!(settings.sequence_length, 0);
+        assert_eq!(settings.encoder.structure, StructureMode::Hierarchical);
+        assert_eq!(settings.encoder.arrays, ArrayMode::Set);
+        assert_eq!(
+            settings.encoder.string_encodings["location"],
+            StringEncoding::Text
+        );
+        assert!(settings.encoder.detect_timestamps);
+        assert_eq!(
+            settings.encoder.field_weights,
+            [("status".to_string(), 3.0)]
+        );
+        assert!(settings.encoder.learn_weights);
+    }
+
+    #[test]
+    fn test_inline_schema_drives_encoder_per_subject() {
+        let settings = settings(&[
+            ("encoder_arrays", "set"),
+            (
+                "schema",
+                r#"{"subjects": [{"subject": "pattern.monitor.auth", "exclude": ["trace_id"]}]}"#,
+            ),
+        ])
+        .unwrap();
+        let auth = settings
+            .encoder_for(settings.schema.as_ref(), "pattern.monitor.auth")
+            .unwrap();
+        assert!(!auth.selects("trace_id"));
+        assert_eq!(auth.arrays, ArrayMode::Set);
+        let other = settings
+            .encoder_for(settings.schema.as_ref(), "pattern.monitor.billing")
+            .unwrap();
+        assert!(other.selects("trace_id"));
+        assert!(settings.schema_key.is_none());
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(settings(&[("novelty_threshold", "high")]).is_err());
+        assert!(settings(&[("novelty_threshold", "1.5")]).is_err());
+        assert!(settings(&[("encoder_structure", "tree")]).is_err());
+        assert!(settings(&[("encoder_max_depth", "0")]).is_err());
+        assert!(settings(&[("bucket", " ")]).is_err());
+        assert!(settings(&[("window_size", "week")]).is_err());
+        assert!(settings(&[("decay_half_life", "100000")]).is_err());
+        assert!(settings(&[("drift_threshold", "-0.1")]).is_err());
+        assert!(settings(&[("prototype_merge_threshold", "2")]).is_err());
+        assert!(settings(&[("sequence_warmup", "-1")]).is_err());
+        assert!(settings(&[("encoder_numeric_ranges", "cpu=100..0")]).is_err());
+        assert!(settings(&[("schema", r#"{"subjects": 3}"#)]).is_err());
+    }
+}
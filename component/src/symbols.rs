@@ -0,0 +1,114 @@
+//! Deterministic role hypervectors used as binding keys.
+//!
+//! `SparseVec::encode_data` maps each byte to a single index, so two encoded
+//! strings rarely share support and binding them (element-wise product over
+//! the shared support) yields an almost empty vector. Role vectors are dense
+//! bipolar instead: binding a value with one keeps the value's full support
+//! and only permutes its signs, which makes the binding lossless and
+//! self-inverse.
+
+use embeddenator_vsa::{SparseVec, DIM};
+use std::collections::BTreeMap;
+
+/// Stable 64-bit FNV-1a hash of `label`, used to seed role vectors and to
+/// place label-specific codes within the vector.
+pub(crate) fn label_hash(label: &str) -> u64 {
+    label.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// SplitMix64 step: portable, stateless-seeded and good enough for sign bits.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Dense bipolar hypervector derived deterministically from `label`.
+///
+/// The same label always yields the same vector on every platform; distinct
+/// labels yield quasi-orthogonal vectors.
+pub(crate) fn role_vector(label: &str) -> SparseVec {
+    let mut state = label_hash(label);
+    let mut pos = Vec::with_capacity(DIM / 2);
+    let mut neg = Vec::with_capacity(DIM / 2);
+    let mut bits = 0u64;
+    for idx in 0..DIM {
+        if idx % 64 == 0 {
+            bits = splitmix64(&mut state);
+        }
+        if bits & 1 == 1 {
+            pos.push(idx);
+        } else {
+            neg.push(idx);
+        }
+        bits >>= 1;
+    }
+    SparseVec { pos, neg }
+}
+
+/// Sparse ternary code with `nnz` non-zero indices derived deterministically
+/// from `label`, for symbolic values (booleans, null) that are bundled rather
+/// than used as binding keys.
+pub(crate) fn sparse_code(label: &str, nnz: usize) -> SparseVec {
+    let mut state = label_hash(label);
+    let mut chosen = BTreeMap::new();
+    while chosen.len() < nnz.min(DIM) {
+        let r = splitmix64(&mut state);
+        chosen.entry((r >> 1) as usize % DIM).or_insert(r & 1 == 1);
+    }
+    let (pos, neg): (Vec<_>, Vec<_>) = chosen.into_iter().partition(|&(_, positive)| positive);
+    SparseVec {
+        pos: pos.into_iter().map(|(idx, _)| idx).collect(),
+        neg: neg.into_iter().map(|(idx, _)| idx).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embeddenator_vsa::ReversibleVSAConfig;
+
+    #[test]
+    fn test_role_vector_is_deterministic_and_dense() {
+        let a = role_vector("status");
+        let b = role_vector("status");
+        assert_eq!(a.pos, b.pos);
+        assert_eq!(a.neg, b.neg);
+        assert_eq!(a.pos.len() + a.neg.len(), DIM);
+    }
+
+    #[test]
+    fn test_distinct_roles_are_quasi_orthogonal() {
+        let sim = role_vector("status").cosine(&role_vector("event"));
+        assert!(
+            sim.abs() < 0.1,
+            "distinct roles should be ~orthogonal, got {sim}"
+        );
+    }
+
+    #[test]
+    fn test_sparse_code_is_deterministic_and_sparse() {
+        let a = sparse_code("bool:true", 16);
+        assert_eq!(a.pos.len() + a.neg.len(), 16);
+        assert_eq!(a.pos, sparse_code("bool:true", 16).pos);
+        assert!(a.cosine(&sparse_code("bool:false", 16)).abs() < 0.2);
+    }
+
+    #[test]
+    fn test_binding_with_role_is_lossless_and_self_inverse() {
+        let value = SparseVec::encode_data(b"\"quake\"", &ReversibleVSAConfig::default(), None);
+        let role = role_vector("event");
+        let bound = role.bind(&value);
+        assert_eq!(
+            bound.pos.len() + bound.neg.len(),
+            value.pos.len() + value.neg.len(),
+            "binding with a dense role must keep the value's support"
+        );
+        let recovered = role.bind(&bound);
+        assert!((recovered.cosine(&value) - 1.0).abs() < 1e-9);
+    }
+}
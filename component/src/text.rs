@@ -0,0 +1,132 @@
+//! Similarity-preserving encoding of free text.
+//!
+//! `encode_data` gives each byte position its own index, so `"Pacific Ocean"`
+//! and `"Pacific Ocean, near Fiji"` share little beyond a common prefix and
+//! any insertion shifts everything after it. The text encoder instead bundles
+//! one symbol per lowercased word and one per character n-gram of each word;
+//! strings sharing words are similar regardless of position, and the n-grams
+//! keep inflections and typos (`quake`, `quakes`) close.
+
+use crate::symbols::sparse_code;
+use embeddenator_vsa::SparseVec;
+use std::collections::HashMap;
+
+/// Length of the character n-grams taken from each word.
+pub(crate) const NGRAM: usize = 3;
+
+/// Non-zero indices in a word symbol.
+pub(crate) const WORD_NNZ: usize = 16;
+
+/// Non-zero indices in an n-gram symbol.
+pub(crate) const NGRAM_NNZ: usize = 4;
+
+/// How string values of a field are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum StringEncoding {
+    /// Raw bytes: only identical strings are similar.
+    #[default]
+    Exact,
+    /// Word and character n-gram bundle, see [`encode_text`].
+    Text,
+}
+
+/// Parse per-field string encodings from a comma-separated spec of
+/// `path=exact|text` entries, e.g. `location=text,notes=text`. An empty
+/// spec yields no entries.
+pub(crate) fn parse_string_encodings(
+    spec: &str,
+) -> Result<HashMap<String, StringEncoding>, String> {
+    let mut encodings = HashMap::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let err = || format!("invalid string encoding '{entry}'");
+        let (path, encoding) = entry.split_once('=').ok_or_else(err)?;
+        let encoding = match encoding.trim() {
+            "exact" => StringEncoding::Exact,
+            "text" => StringEncoding::Text,
+            _ => return Err(err()),
+        };
+        encodings.insert(path.trim().to_string(), encoding);
+    }
+    Ok(encodings)
+}
+
+/// Lowercased alphanumeric words of `text`.
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Character n-grams of `word`, padded with a boundary marker so prefixes
+/// and suffixes are distinguished from the middle of a word.
+fn ngrams(word: &str) -> Vec<String> {
+    let chars: Vec<char> = format!("^{word}$").chars().collect();
+    chars
+        .windows(NGRAM.min(chars.len()))
+        .map(|gram| gram.iter().collect())
+        .collect()
+}
+
+/// Encode `text` as the bundle of its word and character n-gram symbols.
+/// Empty text (no words) encodes to an empty vector.
+pub(crate) fn encode_text(text: &str) -> SparseVec {
+    let mut symbols = Vec::new();
+    for word in tokenize(text) {
+        symbols.extend(
+            ngrams(&word)
+                .iter()
+                .map(|gram| sparse_code(&format!("ngram:{gram}"), NGRAM_NNZ)),
+        );
+        symbols.push(sparse_code(&format!("word:{word}"), WORD_NNZ));
+    }
+    SparseVec::bundle_sum_many(&symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sim(a: &str, b: &str) -> f64 {
+        encode_text(a).cosine(&encode_text(b))
+    }
+
+    #[test]
+    fn test_tokenize_lowercases_and_splits_on_punctuation() {
+        assert_eq!(
+            tokenize("Pacific Ocean, near Fiji!"),
+            ["pacific", "ocean", "near", "fiji"]
+        );
+        assert!(tokenize(" -- ").is_empty());
+    }
+
+    #[test]
+    fn test_ngrams_mark_word_boundaries() {
+        assert_eq!(ngrams("sea"), ["^se", "sea", "ea$"]);
+        assert_eq!(ngrams("a"), ["^a$"]);
+    }
+
+    #[test]
+    fn test_overlapping_text_is_similar() {
+        let extended = sim("Pacific Ocean", "Pacific Ocean, near Fiji");
+        let unrelated = sim("Pacific Ocean", "Atlantic coast");
+        assert!(extended > 0.5, "got {extended}");
+        assert!(unrelated < 0.2, "got {unrelated}");
+        assert!((sim("Pacific Ocean", "pacific  OCEAN") - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_inflections_stay_close() {
+        assert!(sim("quake", "quakes") > sim("quake", "flood"));
+    }
+
+    #[test]
+    fn test_parse_string_encodings_spec() {
+        let encodings = parse_string_encodings("location=text, status=exact").unwrap();
+        assert_eq!(encodings["location"], StringEncoding::Text);
+        assert_eq!(encodings["status"], StringEncoding::Exact);
+        assert!(parse_string_encodings("").unwrap().is_empty());
+        assert!(parse_string_encodings("location").is_err());
+        assert!(parse_string_encodings("location=fuzzy").is_err());
+    }
+}
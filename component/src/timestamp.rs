@@ -0,0 +1,311 @@
+//! Cyclical encoding of timestamps.
+//!
+//! A timestamp is encoded as the bundle of three features, each placed at a
+//! label-specific offset so they never overlap:
+//!
+//! - time of day: a window of [`HOUR_WINDOW`] indices on a ring of
+//!   [`HOUR_SLOTS`] quarter-hour slots, so `23:45` and `00:15` are as close
+//!   as `12:45` and `13:15`, and times six hours apart are unrelated;
+//! - day of week: a window of [`DAY_WINDOW`] on a ring of [`DAY_SLOTS`];
+//! - a coarse absolute bucket (the ISO week since the epoch) as a small
+//!   sparse symbol, so old and recent events differ slightly.
+//!
+//! Cyclic features use the wall-clock time of the timestamp's own UTC
+//! offset, which is what "this normally happens at night" refers to. Epoch
+//! numbers carry no offset and are read as UTC.
+
+use crate::symbols::{label_hash, sparse_code};
+use embeddenator_vsa::{SparseVec, DIM};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Quarter-hour slots on the time-of-day ring.
+pub(crate) const HOUR_SLOTS: usize = 96;
+
+/// Time-of-day window: six hours of slots.
+pub(crate) const HOUR_WINDOW: usize = 24;
+
+/// Quarter-day slots on the day-of-week ring.
+pub(crate) const DAY_SLOTS: usize = 28;
+
+/// Day-of-week window: a day and a half of slots.
+pub(crate) const DAY_WINDOW: usize = 6;
+
+/// Non-zero indices in the absolute week bucket.
+pub(crate) const BUCKET_NNZ: usize = 8;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// How a configured timestamp field is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TimestampFormat {
+    /// RFC 3339 string, e.g. `2024-05-01T03:12:00+02:00`.
+    Rfc3339,
+    /// Unix epoch seconds.
+    EpochSeconds,
+    /// Unix epoch milliseconds.
+    EpochMillis,
+}
+
+/// Parse timestamp fields from a comma-separated spec of
+/// `path[=rfc3339|s|ms]` entries, e.g. `created_at,ts=ms`. The format
+/// defaults to `rfc3339`. An empty spec yields no fields.
+pub(crate) fn parse_timestamp_fields(
+    spec: &str,
+) -> Result<HashMap<String, TimestampFormat>, String> {
+    let mut fields = HashMap::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (path, format) = match entry.split_once('=') {
+            Some((path, format)) => (path.trim(), format.trim()),
+            None => (entry, "rfc3339"),
+        };
+        let format = match format {
+            "rfc3339" => TimestampFormat::Rfc3339,
+            "s" => TimestampFormat::EpochSeconds,
+            "ms" => TimestampFormat::EpochMillis,
+            _ => return Err(format!("invalid timestamp field '{entry}'")),
+        };
+        if path.is_empty() {
+            return Err(format!("invalid timestamp field '{entry}'"));
+        }
+        fields.insert(path.to_string(), format);
+    }
+    Ok(fields)
+}
+
+/// Wall-clock seconds since the epoch (UTC seconds plus the timestamp's own
+/// offset) of `value`, read with `format` if the field is configured, or as
+/// an RFC 3339 string if `detect` is set. `None` if `value` is not a
+/// timestamp.
+pub(crate) fn local_seconds(
+    value: &Value,
+    format: Option<TimestampFormat>,
+    detect: bool,
+) -> Option<i64> {
+    match (format, value) {
+        (Some(TimestampFormat::EpochSeconds), Value::Number(n)) => n.as_f64().map(|s| s as i64),
+        (Some(TimestampFormat::EpochMillis), Value::Number(n)) => {
+            n.as_f64().map(|ms| (ms / 1000.0).floor() as i64)
+        }
+        (Some(TimestampFormat::Rfc3339), Value::String(s)) => parse_rfc3339(s),
+        (None, Value::String(s)) if detect => parse_rfc3339(s),
+        _ => None,
+    }
+}
+
+/// Parse an RFC 3339 date-time into wall-clock seconds since the epoch.
+pub(crate) fn parse_rfc3339(s: &str) -> Option<i64> {
+    let b = s.as_bytes();
+    let num = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = b.get(range)?;
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    if !matches!(b[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+    {
+        return None;
+    }
+    // Leap seconds (`:60`) are accepted and folded into the next second.
+    if second > 60 {
+        return None;
+    }
+    let mut rest = &b[19..];
+    if let Some(fraction) = rest.strip_prefix(b".") {
+        let digits = fraction.iter().take_while(|c| c.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        rest = &fraction[digits..];
+    }
+    match rest {
+        [b'Z' | b'z'] => {}
+        [b'+' | b'-', h1, h2, b':', m1, m2] => {
+            let offset = [h1, h2, m1, m2];
+            if !offset.iter().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            let digit = |c: &u8| i64::from(c - b'0');
+            if digit(h1) * 10 + digit(h2) > 23 || digit(m1) * 10 + digit(m2) > 59 {
+                return None;
+            }
+        }
+        _ => return None,
+    }
+    Some(days_from_civil(year, month, day) * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second)
+}
+
+/// Length of `month` (1 to 12) of `year` in the proleptic Gregorian calendar.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Window of `window` indices starting at `slot` on a ring of `slots`,
+/// placed at an offset derived from `label`.
+fn ring_window(label: &str, slot: usize, slots: usize, window: usize) -> SparseVec {
+    let offset = label_hash(label) as usize % DIM;
+    let mut pos: Vec<usize> = (0..window)
+        .map(|k| (offset + (slot + k) % slots) % DIM)
+        .collect();
+    pos.sort_unstable();
+    SparseVec {
+        pos,
+        neg: Vec::new(),
+    }
+}
+
+/// Encode wall-clock `seconds` since the epoch for the field labelled
+/// `label`.
+pub(crate) fn encode_timestamp(seconds: i64, label: &str) -> SparseVec {
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let second_of_day = seconds.rem_euclid(SECONDS_PER_DAY) as usize;
+    // 1970-01-01 was a Thursday; Monday is day 0.
+    let day_of_week = (days + 3).rem_euclid(7) as usize;
+    let hour_slot = second_of_day * HOUR_SLOTS / SECONDS_PER_DAY as usize;
+    let day_slot = day_of_week * (DAY_SLOTS / 7) + hour_slot * (DAY_SLOTS / 7) / HOUR_SLOTS;
+    let week = (days + 3).div_euclid(7);
+
+    SparseVec::bundle_sum_many(&[
+        ring_window(
+            &format!("{label}#time-of-day"),
+            hour_slot,
+            HOUR_SLOTS,
+            HOUR_WINDOW,
+        ),
+        ring_window(
+            &format!("{label}#day-of-week"),
+            day_slot,
+            DAY_SLOTS,
+            DAY_WINDOW,
+        ),
+        sparse_code(&format!("{label}#week:{week}"), BUCKET_NNZ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn at(s: &str) -> SparseVec {
+        encode_timestamp(parse_rfc3339(s).unwrap(), "ts")
+    }
+
+    #[test]
+    fn test_parse_rfc3339_variants() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2024-02-29T12:30:15Z"), Some(1_709_209_815));
+        // Wall-clock time is kept; the offset is not subtracted.
+        assert_eq!(
+            parse_rfc3339("2024-02-29T12:30:15.250+02:00"),
+            parse_rfc3339("2024-02-29T12:30:15Z")
+        );
+        assert_eq!(parse_rfc3339("2024-02-29 12:30:15z"), Some(1_709_209_815));
+        assert_eq!(parse_rfc3339("2024-02-29T12:30:15"), None);
+        assert_eq!(parse_rfc3339("2024-13-01T00:00:00Z"), None);
+        // Days past the end of the month are not rolled into the next one.
+        assert_eq!(parse_rfc3339("2024-02-30T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("1900-02-29T00:00:00Z"), None);
+        assert!(parse_rfc3339("2000-02-29T00:00:00Z").is_some());
+        assert_eq!(parse_rfc3339("2023-04-31T00:00:00Z"), None);
+        assert!(parse_rfc3339("2023-12-31T00:00:00Z").is_some());
+        assert_eq!(
+            parse_rfc3339("2024-02-29T12:30:15+23:59"),
+            Some(1_709_209_815)
+        );
+        assert_eq!(parse_rfc3339("2024-02-29T12:30:15+24:00"), None);
+        assert_eq!(parse_rfc3339("2024-02-29T12:30:15-02:60"), None);
+        assert_eq!(parse_rfc3339("2024-02-29T12:30:15+0x:00"), None);
+        assert_eq!(parse_rfc3339("quake near the coast"), None);
+    }
+
+    #[test]
+    fn test_time_of_day_is_cyclic() {
+        let late = at("2024-05-01T23:45:00Z");
+        let early = at("2024-05-02T00:15:00Z");
+        let noon = at("2024-05-01T12:00:00Z");
+        assert!(late.cosine(&early) > 0.8, "got {}", late.cosine(&early));
+        // Only the day-of-week and week features are shared.
+        assert!(late.cosine(&noon) < 0.4, "got {}", late.cosine(&noon));
+    }
+
+    #[test]
+    fn test_night_event_is_unlike_daytime_baseline() {
+        let baseline = SparseVec::bundle_sum_many(&[
+            at("2024-05-01T10:00:00Z"),
+            at("2024-05-02T13:30:00Z"),
+            at("2024-05-03T15:00:00Z"),
+        ]);
+        let afternoon = at("2024-05-02T14:00:00Z");
+        let night = at("2024-05-02T03:00:00Z");
+        assert!(afternoon.cosine(&baseline) > night.cosine(&baseline) + 0.2);
+    }
+
+    #[test]
+    fn test_local_seconds_by_format() {
+        assert_eq!(
+            local_seconds(
+                &json!(1_700_000_000),
+                Some(TimestampFormat::EpochSeconds),
+                false
+            ),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            local_seconds(
+                &json!(1_700_000_000_500u64),
+                Some(TimestampFormat::EpochMillis),
+                false
+            ),
+            Some(1_700_000_000)
+        );
+        assert_eq!(
+            local_seconds(&json!("1970-01-01T00:01:00Z"), None, true),
+            Some(60)
+        );
+        assert_eq!(
+            local_seconds(&json!("1970-01-01T00:01:00Z"), None, false),
+            None
+        );
+        assert_eq!(local_seconds(&json!(60), None, true), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp_fields_spec() {
+        let fields = parse_timestamp_fields("created_at, ts=ms,at=s").unwrap();
+        assert_eq!(fields["created_at"], TimestampFormat::Rfc3339);
+        assert_eq!(fields["ts"], TimestampFormat::EpochMillis);
+        assert_eq!(fields["at"], TimestampFormat::EpochSeconds);
+        assert!(parse_timestamp_fields("").unwrap().is_empty());
+        assert!(parse_timestamp_fields("ts=days").is_err());
+    }
+}
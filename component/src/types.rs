@@ -0,0 +1,88 @@
+//! Explicit JSON type information in field vectors.
+//!
+//! Every encoded value's content is bound with a dense type role, so the same
+//! bytes under different types (`"6.2"` vs `6.2`) are unrelated.
+
+use crate::symbols::role_vector;
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The six JSON value types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JsonType {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl JsonType {
+    /// Type of `value`.
+    pub(crate) fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => JsonType::Null,
+            Value::Bool(_) => JsonType::Bool,
+            Value::Number(_) => JsonType::Number,
+            Value::String(_) => JsonType::String,
+            Value::Array(_) => JsonType::Array,
+            Value::Object(_) => JsonType::Object,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            JsonType::Null => "null",
+            JsonType::Bool => "bool",
+            JsonType::Number => "number",
+            JsonType::String => "string",
+            JsonType::Array => "array",
+            JsonType::Object => "object",
+        }
+    }
+
+    /// Dense role that type-tags value content by binding.
+    pub(crate) fn role(self) -> SparseVec {
+        role_vector(&format!("type:{}", self.name()))
+    }
+}
+
+/// Tag leaf `content` with its JSON type: `type_role ⊙ content`.
+pub(crate) fn tag_leaf(json_type: JsonType, content: &SparseVec) -> SparseVec {
+    json_type.role().bind(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_of_json_values() {
+        let value = serde_json::json!([null, true, 1.5, "x", [], {}]);
+        let types: Vec<JsonType> = value.as_array().unwrap().iter().map(JsonType::of).collect();
+        assert_eq!(
+            types,
+            [
+                JsonType::Null,
+                JsonType::Bool,
+                JsonType::Number,
+                JsonType::String,
+                JsonType::Array,
+                JsonType::Object,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_same_content_different_type_is_unrelated() {
+        let content = crate::symbols::sparse_code("content", 1000);
+        let as_string = tag_leaf(JsonType::String, &content);
+        let as_number = tag_leaf(JsonType::Number, &content);
+        let again = tag_leaf(JsonType::String, &content);
+        assert!(as_string.cosine(&as_number).abs() < 0.2);
+        assert!((as_string.cosine(&again) - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,224 @@
+//! Per-field weights and weighted bundling.
+//!
+//! Ternary bundles carry no magnitudes, so a weight cannot simply scale a
+//! field vector. Instead each field keeps a share of its support proportional
+//! to its weight relative to the heaviest field: a field of weight 1 next to
+//! one of weight 3 contributes a third of its indices. The kept indices are
+//! chosen by a hash of the index alone, so a field keeps the same indices in
+//! every message and stays comparable with the baseline. Indices claimed by
+//! several fields take the sign of their weighted vote.
+//!
+//! Weights come from configuration (`field_weights` or the schema) or are
+//! learned from how much each field varies against the baseline.
+
+use crate::schema::path_matches;
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Lowest weight a learned field can get, so even a field that never repeats
+/// stays visible in the bundle.
+pub(crate) const MIN_LEARNED_WEIGHT: f64 = 0.1;
+
+/// Scored messages needed before a field's learned weight is used.
+pub(crate) const MIN_LEARNING_SAMPLES: u64 = 5;
+
+/// Parse field weights from a comma-separated spec of `pattern=weight`
+/// entries, e.g. `status=3,request_id=0`. Patterns are field path patterns
+/// (see [`path_matches`]). An empty spec yields no weights.
+pub(crate) fn parse_field_weights(spec: &str) -> Result<Vec<(String, f64)>, String> {
+    let mut weights = Vec::new();
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let err = || format!("invalid field weight '{entry}'");
+        let (pattern, weight) = entry.split_once('=').ok_or_else(err)?;
+        let weight: f64 = weight.trim().parse().map_err(|_| err())?;
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(err());
+        }
+        weights.push((pattern.trim().to_string(), weight));
+    }
+    Ok(weights)
+}
+
+/// Running variability of one field: the mean novelty (`1 - similarity`) of
+/// the field vector against its subject's baseline.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FieldVariability {
+    pub count: u64,
+    pub mean_novelty: f64,
+}
+
+impl FieldVariability {
+    /// Record one more field similarity.
+    pub(crate) fn record(&mut self, similarity: f64) {
+        let novelty = (1.0 - similarity).clamp(0.0, 1.0);
+        self.count += 1;
+        self.mean_novelty += (novelty - self.mean_novelty) / self.count as f64;
+    }
+
+    /// Learned weight: fields that rarely repeat (ids, nonces) approach
+    /// [`MIN_LEARNED_WEIGHT`], stable fields approach 1. `None` until
+    /// [`MIN_LEARNING_SAMPLES`] have been recorded.
+    pub(crate) fn weight(&self) -> Option<f64> {
+        (self.count >= MIN_LEARNING_SAMPLES)
+            .then(|| (1.0 - self.mean_novelty).max(MIN_LEARNED_WEIGHT))
+    }
+}
+
+/// Weight of each field id: the first configured pattern matching the field
+/// (exact paths first), else its learned weight when `learned` is given,
+/// else 1.
+pub(crate) fn resolve_weights(
+    id_to_field: &HashMap<usize, String>,
+    configured: &[(String, f64)],
+    learned: Option<&BTreeMap<String, FieldVariability>>,
+) -> HashMap<usize, f64> {
+    id_to_field
+        .iter()
+        .map(|(id, field)| {
+            let weight = configured
+                .iter()
+                .find(|(pattern, _)| pattern == field)
+                .or_else(|| {
+                    configured
+                        .iter()
+                        .find(|(pattern, _)| path_matches(pattern, field))
+                })
+                .map(|(_, weight)| *weight)
+                .or_else(|| learned?.get(field)?.weight())
+                .unwrap_or(1.0);
+            (*id, weight)
+        })
+        .collect()
+}
+
+/// Whether `idx` is kept by a field whose weight is `share` of the heaviest.
+fn keeps(idx: usize, share: f64) -> bool {
+    if share >= 1.0 {
+        return true;
+    }
+    // SplitMix64 finaliser as a cheap, portable index hash.
+    let mut z = (idx as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z as f64) < share * u64::MAX as f64
+}
+
+/// Bundle `(vector, weight)` pairs. Zero-weight vectors are left out;
+/// returns `None` if nothing has positive weight.
+pub(crate) fn weighted_bundle<'a, I>(items: I) -> Option<SparseVec>
+where
+    I: IntoIterator<Item = (&'a SparseVec, f64)>,
+{
+    let items: Vec<(&SparseVec, f64)> = items.into_iter().filter(|(_, w)| *w > 0.0).collect();
+    let max_weight = items.iter().map(|(_, w)| *w).fold(0.0, f64::max);
+    if items.is_empty() || max_weight <= 0.0 {
+        return None;
+    }
+
+    let mut votes: BTreeMap<usize, f64> = BTreeMap::new();
+    for (vec, weight) in &items {
+        let share = weight / max_weight;
+        for &idx in vec.pos.iter().filter(|&&idx| keeps(idx, share)) {
+            *votes.entry(idx).or_default() += weight;
+        }
+        for &idx in vec.neg.iter().filter(|&&idx| keeps(idx, share)) {
+            *votes.entry(idx).or_default() -= weight;
+        }
+    }
+
+    let mut bundle = SparseVec::new();
+    for (idx, vote) in votes {
+        if vote > 0.0 {
+            bundle.pos.push(idx);
+        } else if vote < 0.0 {
+            bundle.neg.push(idx);
+        }
+    }
+    Some(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::sparse_code;
+
+    #[test]
+    fn test_equal_weights_keep_every_field() {
+        let a = sparse_code("a", 200);
+        let b = sparse_code("b", 200);
+        let bundle = weighted_bundle([(&a, 1.0), (&b, 1.0)]).unwrap();
+        assert!((bundle.cosine(&a) - bundle.cosine(&b)).abs() < 0.05);
+        assert!(bundle.cosine(&a) > 0.6);
+    }
+
+    #[test]
+    fn test_heavier_field_dominates_bundle() {
+        let critical = sparse_code("critical", 400);
+        let noisy = sparse_code("noisy", 400);
+        let bundle = weighted_bundle([(&critical, 4.0), (&noisy, 1.0)]).unwrap();
+        assert!(bundle.cosine(&critical) > 2.0 * bundle.cosine(&noisy));
+        assert!(bundle.cosine(&noisy) > 0.0);
+    }
+
+    #[test]
+    fn test_zero_weight_fields_are_dropped() {
+        let a = sparse_code("a", 100);
+        let b = sparse_code("b", 100);
+        let bundle = weighted_bundle([(&a, 1.0), (&b, 0.0)]).unwrap();
+        assert!((bundle.cosine(&a) - 1.0).abs() < 1e-9);
+        assert!(weighted_bundle([(&a, 0.0)]).is_none());
+    }
+
+    #[test]
+    fn test_resolve_prefers_exact_then_pattern_then_learned() {
+        let fields = HashMap::from([
+            (0, "status".to_string()),
+            (1, "meta.trace_id".to_string()),
+            (2, "region".to_string()),
+            (3, "event".to_string()),
+        ]);
+        let configured = parse_field_weights("*trace_id=0, status=3, stat*=2").unwrap();
+        let learned = BTreeMap::from([(
+            "region".to_string(),
+            FieldVariability {
+                count: MIN_LEARNING_SAMPLES,
+                mean_novelty: 0.75,
+            },
+        )]);
+        let weights = resolve_weights(&fields, &configured, Some(&learned));
+        assert_eq!(weights[&0], 3.0);
+        assert_eq!(weights[&1], 0.0);
+        assert!((weights[&2] - 0.25).abs() < 1e-9);
+        assert_eq!(weights[&3], 1.0);
+        let unlearned = resolve_weights(&fields, &[], None);
+        assert_eq!(unlearned[&2], 1.0);
+    }
+
+    #[test]
+    fn test_variability_learns_after_enough_samples() {
+        let mut id_field = FieldVariability::default();
+        let mut stable = FieldVariability::default();
+        for _ in 0..MIN_LEARNING_SAMPLES - 1 {
+            id_field.record(0.0);
+            stable.record(0.95);
+        }
+        assert_eq!(id_field.weight(), None);
+        id_field.record(0.0);
+        stable.record(0.95);
+        assert_eq!(id_field.weight(), Some(MIN_LEARNED_WEIGHT));
+        assert!(stable.weight().unwrap() > 0.9);
+    }
+
+    #[test]
+    fn test_parse_field_weights_spec() {
+        assert_eq!(
+            parse_field_weights("status=3, request_id=0").unwrap(),
+            [("status".to_string(), 3.0), ("request_id".to_string(), 0.0)]
+        );
+        assert!(parse_field_weights("").unwrap().is_empty());
+        assert!(parse_field_weights("status=-1").is_err());
+        assert!(parse_field_weights("status").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Time-windowed bundles with rolling retention.
+//!
+//! Next to the all-time master bundle, each subject keeps one bundle per time
+//! window (a minute, an hour or a day, aligned to the Unix epoch), stored
+//! under `bundle-window:v1:{subject}:{size}:{start}` as bincode, where
+//! `start` is the window's first Unix epoch millisecond. The windows stored
+//! for a subject are listed in its [`WindowIndex`], so they are found without
+//! scanning the bucket. Only the newest `window_retention` windows of each
+//! size are kept (at least [`MIN_WINDOW_RETENTION`]); older window keys are
+//! deleted when a new window opens. Comparing the current window with
+//! earlier ones tells "normal this hour" apart from "normal last month".
+
+use crate::anomaly::similarity;
+use crate::baseline::BundleVotes;
+use embeddenator_io::{from_bincode, to_bincode};
+use embeddenator_vsa::SparseVec;
+use serde::{Deserialize, Serialize};
+
+/// Default number of windows kept per subject.
+pub(crate) const DEFAULT_WINDOW_RETENTION: usize = 24;
+
+/// Fewest windows kept: the current window and the one before it, so the
+/// next window to open still finds two complete windows to check for drift.
+pub(crate) const MIN_WINDOW_RETENTION: usize = 2;
+
+/// Length of a window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WindowSize {
+    Minute,
+    #[default]
+    Hour,
+    Day,
+}
+
+impl WindowSize {
+    /// Parse a config value: `minute`, `hour` or `day`.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "minute" => Some(Self::Minute),
+            "hour" => Some(Self::Hour),
+            "day" => Some(Self::Day),
+            _ => None,
+        }
+    }
+
+    /// Name used in keys and config.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    /// Length of the window in milliseconds.
+    pub(crate) fn millis(self) -> u64 {
+        match self {
+            Self::Minute => 60_000,
+            Self::Hour => 3_600_000,
+            Self::Day => 86_400_000,
+        }
+    }
+
+    /// Start of the window holding `at_ms`.
+    pub(crate) fn start_of(self, at_ms: u64) -> u64 {
+        at_ms - at_ms % self.millis()
+    }
+}
+
+/// The bundle of the messages one subject received in one window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct WindowBundle {
+    pub size: WindowSize,
+    /// First Unix epoch millisecond of the window.
+    pub start_ms: u64,
+    /// Number of messages superposed into `bundle`.
+    pub message_count: u64,
+    /// Thresholded `votes`.
+    pub bundle: SparseVec,
+    /// Votes of the window's messages.
+    pub votes: BundleVotes,
+}
+
+/// A window bundle stored before windows kept their votes.
+#[derive(Deserialize)]
+struct LegacyWindowBundle {
+    size: WindowSize,
+    start_ms: u64,
+    message_count: u64,
+    bundle: SparseVec,
+}
+
+impl WindowBundle {
+    /// Open a window with its first message.
+    pub(crate) fn new(size: WindowSize, start_ms: u64, message: &SparseVec) -> Self {
+        let mut votes = BundleVotes::default();
+        votes.add(message);
+        Self {
+            size,
+            start_ms,
+            message_count: 1,
+            bundle: message.clone(),
+            votes,
+        }
+    }
+
+    /// Parse a window bundle read from the bucket. Windows stored without
+    /// votes carry on as if every message had equalled their bundle.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        from_bincode(bytes)
+            .or_else(|_| {
+                from_bincode(bytes).map(|legacy: LegacyWindowBundle| Self {
+                    size: legacy.size,
+                    start_ms: legacy.start_ms,
+                    message_count: legacy.message_count,
+                    votes: BundleVotes::seeded(&legacy.bundle, legacy.message_count),
+                    bundle: legacy.bundle,
+                })
+            })
+            .map_err(|e| format!("window bundle decode error: {e}"))
+    }
+
+    /// Serialise the window bundle for storage in the bucket.
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        to_bincode(self).map_err(|e| format!("window bundle encode error: {e}"))
+    }
+
+    /// Superpose one more message onto the window.
+    pub(crate) fn fold(&mut self, message: &SparseVec) {
+        self.votes.add(message);
+        self.bundle = self.votes.bundle();
+        self.message_count += 1;
+    }
+}
+
+/// Whether a window starting at `start_ms` falls outside the newest
+/// `retention` windows of `size` (at least [`MIN_WINDOW_RETENTION`]),
+/// counting the one holding `now_ms`.
+pub(crate) fn is_expired(start_ms: u64, now_ms: u64, size: WindowSize, retention: usize) -> bool {
+    let retention = retention.max(MIN_WINDOW_RETENTION) as u64;
+    let oldest_kept = size
+        .start_of(now_ms)
+        .saturating_sub(size.millis() * (retention - 1));
+    start_ms < oldest_kept
+}
+
+/// The windows stored for one subject, kept under
+/// `bundle-windows:v1:{subject}` as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct WindowIndex {
+    /// Size and start of each stored window, oldest first.
+    pub windows: Vec<(WindowSize, u64)>,
+}
+
+impl WindowIndex {
+    /// Parse a window index read from the bucket.
+    pub(crate) fn from_json(bytes: &[u8]) -> Result<Self, String> {
+        serde_json::from_slice(bytes).map_err(|e| format!("window index parse error: {e}"))
+    }
+
+    /// Serialise the window index for storage in the bucket.
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("window index encode error: {e}"))
+    }
+
+    /// Record a stored window.
+    pub(crate) fn insert(&mut self, size: WindowSize, start_ms: u64) {
+        if !self.windows.contains(&(size, start_ms)) {
+            self.windows.push((size, start_ms));
+            self.windows
+                .sort_by_key(|(size, start_ms)| (*start_ms, size.millis()));
+        }
+    }
+
+    /// Starts of the two latest windows of `size` that began before
+    /// `before_ms`, older first.
+    pub(crate) fn latest_pair(&self, size: WindowSize, before_ms: u64) -> Option<(u64, u64)> {
+        let mut starts = self
+            .windows
+            .iter()
+            .rev()
+            .filter(|(s, start)| *s == size && *start < before_ms)
+            .map(|(_, start)| *start);
+        let latest = starts.next()?;
+        Some((starts.next()?, latest))
+    }
+
+    /// Remove and return the windows out of retention at `now_ms`, each
+    /// judged by the schedule of its own size.
+    pub(crate) fn expire(&mut self, now_ms: u64, retention: usize) -> Vec<(WindowSize, u64)> {
+        let (expired, kept) = self
+            .windows
+            .iter()
+            .partition(|(size, start)| is_expired(*start, now_ms, *size, retention));
+        self.windows = kept;
+        expired
+    }
+}
+
+/// A window compared with the newest one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct WindowSummary {
+    pub size: WindowSize,
+    pub start_ms: u64,
+    pub message_count: u64,
+    /// Cosine similarity of the window's bundle to the newest window's.
+    pub similarity: f64,
+}
+
+/// Summaries of `windows`, newest first, each compared with the newest.
+pub(crate) fn compare_windows(mut windows: Vec<WindowBundle>) -> Vec<WindowSummary> {
+    windows.sort_by_key(|w| std::cmp::Reverse(w.start_ms));
+    let Some(newest) = windows.first().map(|w| w.bundle.clone()) else {
+        return Vec::new();
+    };
+    windows
+        .into_iter()
+        .map(|w| WindowSummary {
+            size: w.size,
+            start_ms: w.start_ms,
+            message_count: w.message_count,
+            similarity: similarity(&w.bundle, &newest),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::sparse_code;
+
+    #[test]
+    fn test_windows_align_to_their_size() {
+        let at = 1_760_490_123_456;
+        assert_eq!(WindowSize::Minute.start_of(at), 1_760_490_120_000);
+        assert_eq!(WindowSize::Hour.start_of(at), 1_760_490_000_000);
+        assert_eq!(WindowSize::Day.start_of(at), 1_760_486_400_000);
+        assert_eq!(WindowSize::parse("day"), Some(WindowSize::Day));
+        assert_eq!(WindowSize::parse("week"), None);
+        assert_eq!(WindowSize::Hour.as_str(), "hour");
+    }
+
+    #[test]
+    fn test_only_the_newest_windows_are_kept() {
+        let hour = WindowSize::Hour.millis();
+        let now = 10 * hour + 5;
+        assert!(!is_expired(10 * hour, now, WindowSize::Hour, 3));
+        assert!(!is_expired(8 * hour, now, WindowSize::Hour, 3));
+        assert!(is_expired(7 * hour, now, WindowSize::Hour, 3));
+        // The window before the current one is always kept.
+        assert!(!is_expired(9 * hour, now, WindowSize::Hour, 1));
+        assert!(is_expired(8 * hour, now, WindowSize::Hour, 1));
+    }
+
+    #[test]
+    fn test_index_expires_each_size_on_its_own_schedule() {
+        let (minute, hour) = (WindowSize::Minute.millis(), WindowSize::Hour.millis());
+        let now = 10 * hour + 5;
+        let mut index = WindowIndex::default();
+        index.insert(WindowSize::Minute, now - 5 - 3 * minute);
+        index.insert(WindowSize::Hour, 7 * hour);
+        index.insert(WindowSize::Hour, 8 * hour);
+        index.insert(WindowSize::Hour, 9 * hour);
+        index.insert(WindowSize::Hour, 9 * hour);
+        index.insert(WindowSize::Day, 0);
+        assert_eq!(index.windows.len(), 5);
+        assert_eq!(
+            index.latest_pair(WindowSize::Hour, 10 * hour),
+            Some((8 * hour, 9 * hour))
+        );
+        assert_eq!(index.latest_pair(WindowSize::Day, 10 * hour), None);
+
+        // Three hours are kept; the day window is still current, the minute
+        // window three minutes old is not.
+        let expired = index.expire(now, 3);
+        assert_eq!(
+            expired,
+            [
+                (WindowSize::Hour, 7 * hour),
+                (WindowSize::Minute, now - 5 - 3 * minute)
+            ]
+        );
+        assert_eq!(index.windows.len(), 3);
+        let restored = WindowIndex::from_json(&index.to_json().unwrap()).unwrap();
+        assert_eq!(restored, index);
+    }
+
+    #[test]
+    fn test_windows_are_compared_with_the_newest() {
+        let mut current = WindowBundle::new(WindowSize::Hour, 7_200_000, &sparse_code("a", 200));
+        current.fold(&sparse_code("a", 200));
+        let earlier = WindowBundle::new(WindowSize::Hour, 3_600_000, &sparse_code("b", 200));
+        let restored = WindowBundle::from_bytes(&current.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.message_count, 2);
+
+        let summaries = compare_windows(vec![earlier, restored]);
+        assert_eq!(summaries[0].start_ms, 7_200_000);
+        assert!((summaries[0].similarity - 1.0).abs() < 1e-9);
+        assert!(summaries[1].similarity < 0.5);
+        assert!(compare_windows(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_windows_stored_without_votes_still_decode() {
+        let bundle = sparse_code("a", 200);
+        let legacy = to_bincode(&(WindowSize::Hour, 3_600_000u64, 5u64, bundle.clone())).unwrap();
+        let mut window = WindowBundle::from_bytes(&legacy).unwrap();
+        assert_eq!((window.start_ms, window.message_count), (3_600_000, 5));
+        assert_eq!(window.votes, BundleVotes::seeded(&bundle, 5));
+        // One message against five stays out of the bundle.
+        window.fold(&sparse_code("b", 200));
+        assert_eq!(window.bundle.pos, bundle.pos);
+    }
+}
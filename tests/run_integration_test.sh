@@ -37,14 +37,15 @@ PROCESS_WAIT="${PROCESS_WAIT:-10}"
 TEST_SUBJECT="pattern.monitor.integration"
 TEST_PAYLOAD='{"event":"earthquake","magnitude":6.2,"location":"Pacific Ocean","depth_km":35}'
 
-# Expected Redis keys (see component/src/lib.rs key naming constants)
+# Expected Redis keys (see component/src/keys.rs key schema)
 EXPECTED_SEMANTIC_KEYS=(
-    "semantic:v1:event"
-    "semantic:v1:magnitude"
-    "semantic:v1:location"
-    "semantic:v1:depth_km"
+    "semantic:v2:default:${TEST_SUBJECT}:event"
+    "semantic:v2:default:${TEST_SUBJECT}:magnitude"
+    "semantic:v2:default:${TEST_SUBJECT}:location"
+    "semantic:v2:default:${TEST_SUBJECT}:depth_km"
 )
 EXPECTED_BUNDLE_KEY="bundle:v1:${TEST_SUBJECT}"
+EXPECTED_BUNDLE_META_KEY="bundle-meta:v1:${TEST_SUBJECT}"
 
 # ── Colours ───────────────────────────────────────────────────────────────────
 RED='\033[0;31m'
@@ -69,7 +70,7 @@ cleanup() {
 
     # Flush the specific test keys we wrote so we don't leave stale data
     if redis-cli -h "$REDIS_HOST" -p "$REDIS_PORT" PING &>/dev/null 2>&1; then
-        for key in "${EXPECTED_SEMANTIC_KEYS[@]}" "$EXPECTED_BUNDLE_KEY"; do
+        for key in "${EXPECTED_SEMANTIC_KEYS[@]}" "$EXPECTED_BUNDLE_KEY" "$EXPECTED_BUNDLE_META_KEY"; do
             redis-cli -h "$REDIS_HOST" -p "$REDIS_PORT" DEL "$key" &>/dev/null || true
         done
         info "Test keys flushed from Redis"
@@ -273,6 +274,7 @@ done
 
 info "Checking master bundle key:"
 check_key "$EXPECTED_BUNDLE_KEY" || PASS=false
+check_key "$EXPECTED_BUNDLE_META_KEY" || PASS=false
 
 echo ""
 
@@ -288,7 +290,7 @@ if [[ "$PASS" == "true" ]]; then
     info "  JSON message  →  wasmcloud:messaging/handler"
     info "  Fields        →  VSA hypervectors (embeddenator-vsa)"
     info "  Hypervectors  →  bincode bytes (embeddenator-io)"
-    info "  Semantic vecs →  Redis  semantic:v1:{field}"
+    info "  Semantic vecs →  Redis  semantic:v2:{tenant}:{subject}:{field}"
     info "  Master bundle →  Redis  bundle:v1:{subject}"
     exit 0
 else